@@ -1,5 +1,16 @@
-use serde::Deserialize;
+use crate::file::Compression;
+use crate::http::{BulkAction, HttpAuth, HttpOptions};
+use crate::output::{Formatter, LineFormat};
+use crate::schema::Schema;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
 use std::collections::HashMap;
+use std::time::Duration;
+use vapi::vsl::capture::LogCapture;
+use vapi::vsl::filter::LogFilter;
+use vapi::vsl::ncsa::NcsaFormat;
+use vapi::vsl::redact::{IpAnonymization, RedactAction, RedactTarget, Redactor};
 use vapi::vsl::transform::LogTransform;
 use vapi::vsl::IpSource;
 use vapi::{LogGrouping, Reason, TxType};
@@ -20,6 +31,30 @@ fn default_tcp_sender_threads() -> u64 {
     2
 }
 
+fn default_http_index() -> String {
+    "varnish-%Y.%m.%d".to_string()
+}
+
+fn default_batch_size() -> usize {
+    500
+}
+
+fn default_batch_age_ms() -> u64 {
+    5000
+}
+
+fn default_http_timeout() -> u64 {
+    30
+}
+
+fn default_max_retries() -> u32 {
+    5
+}
+
+fn default_retry_backoff_ms() -> u64 {
+    500
+}
+
 fn default_metrics_address() -> String {
     "127.0.0.1".to_string()
 }
@@ -32,7 +67,7 @@ fn default_metrics_threads() -> usize {
     1
 }
 
-#[derive(Debug, Deserialize, Default)]
+#[derive(Debug, Clone, Deserialize, Default)]
 pub struct InputConfig {
     #[serde(default = "default_shm_connect_timeout")]
     pub connect_timeout_secs: u64,
@@ -41,7 +76,7 @@ pub struct InputConfig {
 
 #[derive(Debug, Deserialize, Default)]
 #[serde(tag = "destination", rename_all = "snake_case")]
-pub enum OutputConfig {
+pub enum Destination {
     #[default]
     Stdout,
     Tcp {
@@ -54,9 +89,98 @@ pub enum OutputConfig {
         #[serde(default = "default_tcp_sender_threads")]
         sender_threads: u64,
     },
+    File {
+        path: String,
+        rotate_bytes: Option<u64>,
+        rotate_interval_secs: Option<u64>,
+        #[serde(default)]
+        compression: Compression,
+        keep: Option<usize>,
+    },
+    Http {
+        url: String,
+        #[serde(default = "default_http_index")]
+        index: String,
+        #[serde(default)]
+        bulk_action: BulkAction,
+        #[serde(default = "default_batch_size")]
+        batch_size: usize,
+        #[serde(default = "default_batch_age_ms")]
+        batch_age_ms: u64,
+        #[serde(default)]
+        gzip: bool,
+        username: Option<String>,
+        password: Option<String>,
+        password_env: Option<String>,
+        bearer_token: Option<String>,
+        bearer_token_env: Option<String>,
+        #[serde(default = "default_http_timeout")]
+        timeout_secs: u64,
+        #[serde(default = "default_max_retries")]
+        max_retries: u32,
+        #[serde(default = "default_retry_backoff_ms")]
+        retry_backoff_ms: u64,
+    },
     Null,
 }
 
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    #[default]
+    Json,
+    Pretty,
+    Logfmt,
+    Csv,
+    Tsv,
+    Ncsa,
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub struct OutputConfig {
+    #[serde(flatten)]
+    pub destination: Destination,
+    #[serde(default)]
+    pub format: OutputFormat,
+    #[serde(default)]
+    pub columns: Vec<String>,
+    pub ncsa_format: Option<String>,
+    pub schema: Option<SchemaConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct SchemaConfig {
+    pub fields: HashMap<String, String>,
+    pub keep_unmapped: bool,
+    pub drop: Vec<String>,
+    pub constants: HashMap<String, Value>,
+    pub flatten: bool,
+}
+
+impl Default for SchemaConfig {
+    fn default() -> Self {
+        Self {
+            fields: HashMap::new(),
+            keep_unmapped: true,
+            drop: Vec::new(),
+            constants: HashMap::new(),
+            flatten: false,
+        }
+    }
+}
+
+// serde can't default the tag of a flattened enum, so fill in `destination` first
+fn output_config<'de, D: Deserializer<'de>>(d: D) -> Result<OutputConfig, D::Error> {
+    let mut table = toml::Table::deserialize(d)?;
+    table
+        .entry("destination")
+        .or_insert_with(|| toml::Value::from("stdout"));
+    toml::Value::Table(table)
+        .try_into()
+        .map_err(serde::de::Error::custom)
+}
+
 #[derive(Debug, Deserialize)]
 pub struct MetricsConfig {
     pub enabled: bool,
@@ -66,6 +190,8 @@ pub struct MetricsConfig {
     pub port: u16,
     #[serde(default = "default_metrics_threads")]
     pub worker_threads: usize,
+    #[serde(default)]
+    pub varnish_stats: bool,
 }
 
 impl Default for MetricsConfig {
@@ -75,6 +201,7 @@ impl Default for MetricsConfig {
             address: default_metrics_address(),
             port: default_metrics_port(),
             worker_threads: default_metrics_threads(),
+            varnish_stats: false,
         }
     }
 }
@@ -84,6 +211,7 @@ impl Default for MetricsConfig {
 pub enum Grouping {
     Vxid,
     Request,
+    Session,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
@@ -134,58 +262,428 @@ impl From<ReasonType> for Reason {
     }
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RedactField {
+    Query,
+    Header,
+    RemoteIp,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RedactMode {
+    Drop,
+    Mask,
+    Hash,
+}
+
+fn default_mask() -> String {
+    String::from("REDACTED")
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RedactRuleConfig {
+    pub field: RedactField,
+    #[serde(default)]
+    pub names: Vec<String>,
+    pub action: RedactMode,
+    #[serde(default = "default_mask")]
+    pub mask: String,
+    pub hash_length: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct RedactionConfig {
+    pub hash_key: Option<String>,
+    pub hash_key_env: Option<String>,
+    pub rules: Vec<RedactRuleConfig>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnonymizeMode {
+    Truncate,
+    Hash,
+}
+
+fn default_ipv4_prefix() -> u8 {
+    24
+}
+
+fn default_ipv6_prefix() -> u8 {
+    48
+}
+
+#[derive(Debug, Deserialize)]
+pub struct IpAnonymizationConfig {
+    pub mode: AnonymizeMode,
+    #[serde(default = "default_ipv4_prefix")]
+    pub ipv4_prefix: u8,
+    #[serde(default = "default_ipv6_prefix")]
+    pub ipv6_prefix: u8,
+    pub hash_key: Option<String>,
+    pub hash_key_env: Option<String>,
+    pub hash_length: Option<usize>,
+    pub original_ip_path: Option<String>,
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(default)]
 pub struct LoggingConfig {
     pub track_headers: bool,
+    pub header_history: bool,
     pub request_headers: Vec<String>,
     pub response_headers: Vec<String>,
+    pub request_header_patterns: Vec<String>,
+    pub response_header_patterns: Vec<String>,
+    pub capture_all_headers: bool,
+    pub deny_headers: Vec<String>,
     pub tags: HashMap<String, String>,
     pub query: String,
     #[serde(flatten)]
     pub ip_source: IpSource,
     #[serde(with = "Grouping")]
     pub grouping: LogGrouping,
+    pub nest_transactions: bool,
+    pub vcl_log: bool,
     pub type_filter: Vec<LogType>,
     pub reason_filter: Vec<ReasonType>,
     pub tail: bool,
+    pub redaction: Option<RedactionConfig>,
+    pub ip_anonymization: Option<IpAnonymizationConfig>,
 }
 
 impl Default for LoggingConfig {
     fn default() -> Self {
         Self {
             track_headers: false,
+            header_history: false,
             request_headers: Vec::new(),
             response_headers: Vec::new(),
+            request_header_patterns: Vec::new(),
+            response_header_patterns: Vec::new(),
+            capture_all_headers: false,
+            deny_headers: Vec::new(),
             tags: HashMap::new(),
             query: String::new(),
             ip_source: IpSource::Request,
             grouping: LogGrouping::Vxid,
+            nest_transactions: false,
+            vcl_log: false,
             type_filter: Vec::new(),
             reason_filter: Vec::new(),
             tail: true,
+            redaction: None,
+            ip_anonymization: None,
         }
     }
 }
 
+#[derive(Debug, Deserialize)]
+pub struct CaptureConfig {
+    pub path: String,
+    pub rotate_bytes: Option<u64>,
+    pub rotate_interval_secs: Option<u64>,
+    pub keep: Option<usize>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Config {
     #[serde(default)]
     pub input: InputConfig,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "output_config")]
     pub output: OutputConfig,
     #[serde(default)]
     pub logging: LoggingConfig,
     #[serde(default)]
     pub metrics: MetricsConfig,
+    pub capture: Option<CaptureConfig>,
+}
+
+// exact names and globs from `names` plus regexes from `patterns`
+fn header_filter(names: &[String], patterns: &[String]) -> Result<LogFilter> {
+    let mut filters: Vec<LogFilter> = names
+        .iter()
+        .map(|n| LogFilter::glob(&n.to_lowercase()))
+        .collect();
+    for p in patterns {
+        filters
+            .push(LogFilter::pattern(p).with_context(|| format!("Invalid header pattern {}", p))?);
+    }
+    Ok(LogFilter::or(filters))
+}
+
+// `value` as given, or read from the environment variable `var`
+fn secret(value: &Option<String>, var: &Option<String>, what: &str) -> Result<Option<String>> {
+    Ok(match (value, var) {
+        (Some(v), _) => Some(v.clone()),
+        (None, Some(var)) => Some(
+            std::env::var(var).with_context(|| format!("Couldn't read {} from ${}", what, var))?,
+        ),
+        (None, None) => None,
+    })
+}
+
+pub fn redactor_from_config(config: &RedactionConfig) -> Result<Redactor> {
+    let key = secret(&config.hash_key, &config.hash_key_env, "hash key")?;
+    let uses_hash = config.rules.iter().any(|r| r.action == RedactMode::Hash);
+    if uses_hash && key.as_deref().unwrap_or("").is_empty() {
+        bail!("Redaction rules that hash need hash_key or hash_key_env");
+    }
+    let mut redactor = Redactor::new(key.unwrap_or_default());
+    for rule in &config.rules {
+        // query parameter names are case sensitive, header names are matched lowercased
+        let target = match rule.field {
+            RedactField::Query => RedactTarget::QueryParam(LogFilter::or(
+                rule.names.iter().map(|n| LogFilter::glob(n)),
+            )),
+            RedactField::Header => RedactTarget::Header(LogFilter::or(
+                rule.names
+                    .iter()
+                    .map(|n| LogFilter::glob(&n.to_lowercase())),
+            )),
+            RedactField::RemoteIp => RedactTarget::RemoteIp,
+        };
+        let action = match rule.action {
+            RedactMode::Drop => RedactAction::Drop,
+            RedactMode::Mask => RedactAction::Mask(rule.mask.clone()),
+            RedactMode::Hash => RedactAction::Hash {
+                length: rule.hash_length,
+            },
+        };
+        redactor = redactor.rule(target, action);
+    }
+    Ok(redactor)
 }
 
-pub fn transform_from_config(config: &LoggingConfig) -> LogTransform {
-    let t = LogTransform::new();
+pub fn anonymization_from_config(config: &IpAnonymizationConfig) -> Result<IpAnonymization> {
+    match config.mode {
+        AnonymizeMode::Truncate => {
+            if config.ipv4_prefix > 32 || config.ipv6_prefix > 128 {
+                bail!("ipv4_prefix can be at most 32 and ipv6_prefix at most 128");
+            }
+            Ok(IpAnonymization::Truncate {
+                v4: config.ipv4_prefix,
+                v6: config.ipv6_prefix,
+            })
+        }
+        AnonymizeMode::Hash => {
+            let key =
+                secret(&config.hash_key, &config.hash_key_env, "hash key")?.unwrap_or_default();
+            if key.is_empty() {
+                bail!("IP anonymization with mode = \"hash\" needs hash_key or hash_key_env");
+            }
+            Ok(IpAnonymization::Hash {
+                key: key.into_bytes(),
+                length: config.hash_length,
+            })
+        }
+    }
+}
+
+pub fn schema_from_config(config: &SchemaConfig) -> Result<Schema> {
+    let mut constants = Vec::new();
+    for (name, value) in &config.constants {
+        // "${VAR}" is filled in from the environment at startup
+        let value = match value
+            .as_str()
+            .and_then(|v| v.strip_prefix("${")?.strip_suffix('}'))
+        {
+            Some(var) => Value::from(std::env::var(var).with_context(|| {
+                format!("Couldn't read schema constant {} from ${}", name, var)
+            })?),
+            None => value.clone(),
+        };
+        constants.push((name.clone(), value));
+    }
+    // sorted so that "url" is set before "url.original" replaces it
+    constants.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut fields: Vec<(String, String)> = config
+        .fields
+        .iter()
+        .map(|(name, source)| (name.clone(), source.clone()))
+        .collect();
+    fields.sort();
+    Ok(Schema {
+        fields,
+        keep_unmapped: config.keep_unmapped,
+        drop: config.drop.clone(),
+        constants,
+        flatten: config.flatten,
+    })
+}
 
-    t.req_headers(&config.request_headers)
-        .resp_headers(&config.response_headers)
-        .track_headers(config.track_headers)
+// settings the output thread would only trip over once it's running
+fn check_destination(destination: &Destination) -> Result<()> {
+    match destination {
+        Destination::File {
+            rotate_bytes,
+            rotate_interval_secs,
+            ..
+        } if *rotate_bytes == Some(0) || *rotate_interval_secs == Some(0) => {
+            bail!("rotate_bytes and rotate_interval_secs have to be at least 1");
+        }
+        Destination::Http { .. } => {
+            http_from_config(destination)?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+pub fn formatter_from_config(config: &OutputConfig) -> Result<Formatter> {
+    let columns = config.columns.clone();
+    let format = match config.format {
+        OutputFormat::Json => LineFormat::Json,
+        OutputFormat::Pretty => LineFormat::Pretty,
+        OutputFormat::Logfmt => LineFormat::Logfmt(columns),
+        OutputFormat::Csv | OutputFormat::Tsv if columns.is_empty() => {
+            bail!("format = \"csv\" and \"tsv\" need a list of columns")
+        }
+        OutputFormat::Csv => LineFormat::Delimited {
+            delimiter: ',',
+            columns,
+        },
+        OutputFormat::Tsv => LineFormat::Delimited {
+            delimiter: '\t',
+            columns,
+        },
+        OutputFormat::Ncsa => LineFormat::Ncsa(match &config.ncsa_format {
+            Some(f) => NcsaFormat::parse(f).context("Invalid ncsa_format")?,
+            None => NcsaFormat::default(),
+        }),
+    };
+    check_destination(&config.destination)?;
+    if matches!(config.destination, Destination::Http { .. }) && config.format != OutputFormat::Json
+    {
+        bail!("destination = \"http\" only supports format = \"json\"");
+    }
+    if config.schema.is_some() && config.format == OutputFormat::Ncsa {
+        bail!("[output.schema] can't be used with format = \"ncsa\"");
+    }
+    Ok(Formatter {
+        format,
+        schema: config.schema.as_ref().map(schema_from_config).transpose()?,
+    })
+}
+
+fn http_auth(
+    username: &Option<String>,
+    password: Option<String>,
+    bearer_token: Option<String>,
+) -> Result<HttpAuth> {
+    match (username, password, bearer_token) {
+        (None, None, None) => Ok(HttpAuth::None),
+        (Some(username), Some(password), None) => Ok(HttpAuth::Basic {
+            username: username.clone(),
+            password,
+        }),
+        (None, None, Some(token)) => Ok(HttpAuth::Bearer(token)),
+        (_, _, Some(_)) => bail!("Use either username and password or bearer_token, not both"),
+        _ => bail!("Basic auth needs both username and password"),
+    }
+}
+
+pub fn http_from_config(config: &Destination) -> Result<HttpOptions> {
+    let Destination::Http {
+        url,
+        index,
+        bulk_action,
+        batch_size,
+        batch_age_ms,
+        gzip,
+        username,
+        password,
+        password_env,
+        bearer_token,
+        bearer_token_env,
+        timeout_secs,
+        max_retries,
+        retry_backoff_ms,
+    } = config
+    else {
+        bail!("Not an http destination");
+    };
+    let auth = http_auth(
+        username,
+        secret(password, password_env, "password")?,
+        secret(bearer_token, bearer_token_env, "bearer token")?,
+    )?;
+    let opts = HttpOptions {
+        url: url.clone(),
+        index: index.clone(),
+        action: *bulk_action,
+        batch_size: *batch_size,
+        batch_age: Duration::from_millis(*batch_age_ms),
+        gzip: *gzip,
+        auth,
+        timeout: Duration::from_secs(*timeout_secs),
+        max_retries: *max_retries,
+        retry_backoff: Duration::from_millis(*retry_backoff_ms),
+    };
+    opts.validate()?;
+    Ok(opts)
+}
+
+/// `ncsa` adds whatever the access log format needs to what's configured.
+pub fn transform_from_config(
+    config: &LoggingConfig,
+    ncsa: Option<&NcsaFormat>,
+) -> Result<LogTransform> {
+    let mut request_headers = config.request_headers.clone();
+    let mut response_headers = config.response_headers.clone();
+    if let Some(f) = ncsa {
+        request_headers.extend(f.req_headers());
+        response_headers.extend(f.resp_headers());
+    }
+    let mut t = LogTransform::new()
+        .req_header_filter(header_filter(
+            &request_headers,
+            &config.request_header_patterns,
+        )?)
+        .resp_header_filter(header_filter(
+            &response_headers,
+            &config.response_header_patterns,
+        )?);
+    if config.capture_all_headers {
+        t = t.capture_all_headers();
+    }
+    if !config.deny_headers.is_empty() {
+        t = t.deny_headers(&config.deny_headers);
+    }
+    if let Some(redaction) = &config.redaction {
+        t = t.redact(redactor_from_config(redaction)?);
+    }
+    if let Some(anon) = &config.ip_anonymization {
+        t = t
+            .anonymize_ip(anonymization_from_config(anon)?)
+            .keep_original_ip(anon.original_ip_path.is_some());
+    }
+
+    Ok(t.track_headers(config.track_headers)
+        .header_history(config.header_history)
         .meta(config.tags.clone())
         .ip_source(&config.ip_source)
+        .nest_transactions(config.nest_transactions)
+        .vcl_log(config.vcl_log || ncsa.is_some_and(|f| f.uses_vcl_log()))
+        .remote_user(ncsa.is_some_and(|f| f.uses_remote_user())))
+}
+
+pub fn capture_from_config(config: &CaptureConfig) -> Result<LogCapture> {
+    if config.rotate_bytes == Some(0) || config.rotate_interval_secs == Some(0) {
+        bail!("rotate_bytes and rotate_interval_secs have to be at least 1");
+    }
+    let mut c = LogCapture::new(&config.path);
+    if let Some(bytes) = config.rotate_bytes {
+        c = c.rotate_size(bytes);
+    }
+    if let Some(secs) = config.rotate_interval_secs {
+        c = c.rotate_interval(Duration::from_secs(secs));
+    }
+    if let Some(keep) = config.keep {
+        c = c.keep(keep);
+    }
+    Ok(c)
 }
@@ -0,0 +1,252 @@
+use crate::metrics::ROTATE_COUNTER;
+use serde::Deserialize;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+use vapi::rotate::{prune_rotated, rotated_path};
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+// what rotated files can end with, whatever the current setting, so older files
+// are still pruned after it changes
+const EXTENSIONS: &[&str] = &["gz", "zst"];
+
+impl Compression {
+    fn extension(self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some("gz"),
+            Compression::Zstd => Some("zst"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FileOptions {
+    pub path: PathBuf,
+    pub rotate_bytes: Option<u64>,
+    pub rotate_interval: Option<Duration>,
+    pub compression: Compression,
+    pub keep: Option<usize>,
+}
+
+/// An append-only log file that rotates by size and age, and reopens its path
+/// whenever `reopen` is set, so it can also be rotated by logrotate.
+pub struct RotatingFile {
+    opts: FileOptions,
+    file: BufWriter<File>,
+    written: u64,
+    opened: Instant,
+    reopen: Arc<AtomicBool>,
+    // compression and pruning of the last rotated file
+    pending: Option<JoinHandle<()>>,
+}
+
+fn open_file(path: &Path) -> io::Result<(BufWriter<File>, u64)> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let len = file.metadata()?.len();
+    Ok((BufWriter::new(file), len))
+}
+
+fn compressed_path(path: &Path, ext: &str) -> PathBuf {
+    PathBuf::from(format!("{}.{}", path.display(), ext))
+}
+
+fn compress(path: &Path, compression: Compression) -> io::Result<()> {
+    let ext = match compression.extension() {
+        Some(ext) => ext,
+        None => return Ok(()),
+    };
+    let mut input = File::open(path)?;
+    let output = File::create(compressed_path(path, ext))?;
+    match compression {
+        Compression::Gzip => {
+            let mut enc = flate2::write::GzEncoder::new(output, flate2::Compression::default());
+            io::copy(&mut input, &mut enc)?;
+            enc.finish()?;
+        }
+        Compression::Zstd => {
+            let mut enc = zstd::Encoder::new(output, 0)?;
+            io::copy(&mut input, &mut enc)?;
+            enc.finish()?;
+        }
+        Compression::None => {}
+    }
+    fs::remove_file(path)
+}
+
+impl RotatingFile {
+    pub fn open(opts: FileOptions, reopen: Arc<AtomicBool>) -> io::Result<RotatingFile> {
+        let (file, written) = open_file(&opts.path)?;
+        Ok(RotatingFile {
+            opts,
+            file,
+            written,
+            opened: Instant::now(),
+            reopen,
+            pending: None,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.opts.path
+    }
+
+    pub fn write(&mut self, line: &[u8]) -> io::Result<()> {
+        self.tick()?;
+        let res = self.file.write_all(line);
+        if res.is_err() {
+            // the file may have been removed from under us, try a fresh one next time
+            self.reopen.store(true, Ordering::Relaxed);
+        }
+        res?;
+        self.written += line.len() as u64;
+        if self
+            .opts
+            .rotate_bytes
+            .is_some_and(|max| self.written >= max)
+        {
+            self.rotate()?;
+        }
+        Ok(())
+    }
+
+    /// Reopen or rotate the file if it's due, without writing anything.
+    pub fn tick(&mut self) -> io::Result<()> {
+        if self.reopen.swap(false, Ordering::Relaxed) {
+            self.file.flush()?;
+            let (file, written) = open_file(&self.opts.path)?;
+            self.file = file;
+            self.written = written;
+            info!("Reopened {}", self.opts.path.display());
+        }
+        let too_old = self
+            .opts
+            .rotate_interval
+            .is_some_and(|age| self.opened.elapsed() >= age);
+        if too_old {
+            if self.written > 0 {
+                self.rotate()?;
+            } else {
+                self.opened = Instant::now();
+            }
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let compression = self.opts.compression;
+        let rotated = rotated_path(&self.opts.path, EXTENSIONS)?;
+        fs::rename(&self.opts.path, &rotated)?;
+        let (file, written) = open_file(&self.opts.path)?;
+        self.file = file;
+        self.written = written;
+        self.opened = Instant::now();
+        ROTATE_COUNTER.inc();
+        info!("Rotated log file to {}", rotated.display());
+
+        // one at a time, so pruning never races a file that's still being compressed
+        if let Some(h) = self.pending.take() {
+            let _ = h.join();
+        }
+        let path = self.opts.path.clone();
+        let keep = self.opts.keep;
+        self.pending = Some(std::thread::spawn(move || {
+            if let Err(e) = compress(&rotated, compression) {
+                warn!("Couldn't compress {}: {}", rotated.display(), e);
+            }
+            if let Some(keep) = keep {
+                if let Err(e) = prune_rotated(&path, keep, EXTENSIONS) {
+                    warn!("Couldn't remove old log files: {}", e);
+                }
+            }
+        }));
+        Ok(())
+    }
+
+    /// Flush, and wait for rotated files to be compressed.
+    pub fn finish(mut self) -> io::Result<()> {
+        if let Some(h) = self.pending.take() {
+            let _ = h.join();
+        }
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_rotate_and_compress() {
+        let dir = std::env::temp_dir().join(format!("vapi-logger-file-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("access.log");
+        let opts = FileOptions {
+            path: path.clone(),
+            rotate_bytes: Some(10),
+            rotate_interval: None,
+            compression: Compression::Gzip,
+            keep: Some(2),
+        };
+        // left by logrotate, not ours to prune
+        fs::write(dir.join("access.log.1.gz"), "").unwrap();
+        let reopen = Arc::new(AtomicBool::new(false));
+        let mut f = RotatingFile::open(opts, reopen.clone()).unwrap();
+        for i in 0..4 {
+            f.write(format!("{{\"n\":{}}}\n", i).as_bytes()).unwrap();
+            f.write(b"{}\n").unwrap();
+        }
+        f.write(b"{}\n").unwrap();
+        // logrotate moved it away, the next write goes to a new file
+        fs::rename(&path, dir.join("moved")).unwrap();
+        reopen.store(true, Ordering::Relaxed);
+        f.write(b"{\"n\":9}\n").unwrap();
+        f.finish().unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{\"n\":9}\n");
+        assert_eq!(fs::read_to_string(dir.join("moved")).unwrap(), "{}\n");
+        let rotated: Vec<PathBuf> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .filter(|p| p.to_string_lossy().contains("access.log."))
+            .filter(|p| !p.ends_with("access.log.1.gz"))
+            .collect();
+        assert_eq!(rotated.len(), 2);
+        let mut texts: Vec<String> = rotated
+            .iter()
+            .map(|p| {
+                assert!(p.to_string_lossy().ends_with(".gz"));
+                let mut text = String::new();
+                flate2::read::GzDecoder::new(File::open(p).unwrap())
+                    .read_to_string(&mut text)
+                    .unwrap();
+                text
+            })
+            .collect();
+        texts.sort();
+        // only the two newest are kept
+        assert_eq!(texts, vec!["{\"n\":2}\n{}\n", "{\"n\":3}\n{}\n"]);
+        assert!(dir.join("access.log.1.gz").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
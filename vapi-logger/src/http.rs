@@ -0,0 +1,338 @@
+use crate::metrics::{HTTP_DROPPED_COUNTER, HTTP_FAILURE_COUNTER, HTTP_RETRY_COUNTER};
+use anyhow::{bail, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Utc};
+use flate2::write::GzEncoder;
+use serde::Deserialize;
+use serde_json::Value;
+use std::io::{self, Write};
+use std::time::Duration;
+use tracing::{error, warn};
+use vapi::vsl::LogRecord;
+
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BulkAction {
+    #[default]
+    Index,
+    /// Needed to write to data streams
+    Create,
+}
+
+#[derive(Debug, Clone)]
+pub enum HttpAuth {
+    None,
+    Basic { username: String, password: String },
+    Bearer(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpOptions {
+    pub url: String,
+    /// strftime pattern, filled in with the time the request started, in UTC
+    pub index: String,
+    pub action: BulkAction,
+    pub batch_size: usize,
+    pub batch_age: Duration,
+    pub gzip: bool,
+    pub auth: HttpAuth,
+    pub timeout: Duration,
+    pub max_retries: u32,
+    pub retry_backoff: Duration,
+}
+
+impl HttpOptions {
+    pub fn validate(&self) -> Result<()> {
+        if StrftimeItems::new(&self.index).any(|i| matches!(i, Item::Error)) {
+            bail!("Invalid index pattern {}", self.index);
+        }
+        if self.batch_size == 0 {
+            bail!("batch_size has to be at least 1");
+        }
+        if self.batch_age.is_zero() {
+            bail!("batch_age_ms has to be at least 1");
+        }
+        Ok(())
+    }
+}
+
+// why a bulk request didn't go through
+enum SendError {
+    Retry(String),
+    Fatal(String),
+}
+
+/// Sends batches of JSON documents to an Elasticsearch compatible `_bulk` endpoint.
+pub struct BulkSender {
+    opts: HttpOptions,
+    url: String,
+    agent: ureq::Agent,
+    authorization: Option<String>,
+}
+
+impl BulkSender {
+    pub fn new(opts: HttpOptions) -> BulkSender {
+        let agent = ureq::AgentBuilder::new().timeout(opts.timeout).build();
+        let authorization = match &opts.auth {
+            HttpAuth::None => None,
+            HttpAuth::Basic { username, password } => Some(format!(
+                "Basic {}",
+                BASE64.encode(format!("{}:{}", username, password))
+            )),
+            HttpAuth::Bearer(token) => Some(format!("Bearer {}", token)),
+        };
+        let url = format!("{}/_bulk", opts.url.trim_end_matches('/'));
+        BulkSender {
+            opts,
+            url,
+            agent,
+            authorization,
+        }
+    }
+
+    pub fn options(&self) -> &HttpOptions {
+        &self.opts
+    }
+
+    pub fn index_name(&self, log: &LogRecord) -> String {
+        let time = log
+            .timings
+            .get("Start")
+            .and_then(|t| DateTime::from_timestamp(t.ts as i64, 0))
+            .unwrap_or_else(Utc::now);
+        time.format(&self.opts.index).to_string()
+    }
+
+    fn body(&self, docs: &[(String, String)]) -> io::Result<Vec<u8>> {
+        let action = match self.opts.action {
+            BulkAction::Index => "index",
+            BulkAction::Create => "create",
+        };
+        let mut body = Vec::new();
+        for (index, doc) in docs {
+            let meta = serde_json::json!({ action: { "_index": index } });
+            writeln!(body, "{}", meta)?;
+            writeln!(body, "{}", doc)?;
+        }
+        if self.opts.gzip {
+            let mut enc = GzEncoder::new(Vec::new(), flate2::Compression::default());
+            enc.write_all(&body)?;
+            body = enc.finish()?;
+        }
+        Ok(body)
+    }
+
+    fn post(&self, docs: &[(String, String)]) -> std::result::Result<Value, SendError> {
+        let body = self
+            .body(docs)
+            .map_err(|e| SendError::Fatal(format!("Couldn't build request: {}", e)))?;
+        let mut req = self
+            .agent
+            .post(&self.url)
+            .set("Content-Type", "application/x-ndjson");
+        if self.opts.gzip {
+            req = req.set("Content-Encoding", "gzip");
+        }
+        if let Some(auth) = &self.authorization {
+            req = req.set("Authorization", auth);
+        }
+        match req.send_bytes(&body) {
+            Ok(resp) => resp
+                .into_json()
+                .map_err(|e| SendError::Retry(format!("Invalid bulk response: {}", e))),
+            Err(ureq::Error::Status(status, resp)) => {
+                let msg = format!(
+                    "{} from {}: {}",
+                    status,
+                    self.url,
+                    resp.into_string().unwrap_or_default()
+                );
+                if status == 429 || status >= 500 {
+                    Err(SendError::Retry(msg))
+                } else {
+                    Err(SendError::Fatal(msg))
+                }
+            }
+            Err(e) => Err(SendError::Retry(e.to_string())),
+        }
+    }
+
+    /// Send `docs`, pairs of index name and JSON document, retrying the request or the
+    /// documents that were turned away with a 429. Returns how many documents were dropped.
+    pub fn send(&self, mut docs: Vec<(String, String)>) -> usize {
+        let mut backoff = self.opts.retry_backoff;
+        let mut dropped = 0;
+        for attempt in 0..=self.opts.max_retries {
+            if attempt > 0 {
+                HTTP_RETRY_COUNTER.inc();
+                std::thread::sleep(backoff);
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+            match self.post(&docs) {
+                Ok(resp) => {
+                    let (retry, failed) = rejected_items(&resp);
+                    if failed > 0 {
+                        warn!("{} documents rejected by {}", failed, self.url);
+                        dropped += failed;
+                    }
+                    if retry.is_empty() {
+                        HTTP_DROPPED_COUNTER.inc_by(dropped as u64);
+                        return dropped;
+                    }
+                    docs = docs
+                        .into_iter()
+                        .enumerate()
+                        .filter(|(i, _)| retry.contains(i))
+                        .map(|(_, d)| d)
+                        .collect();
+                }
+                Err(SendError::Retry(e)) => {
+                    HTTP_FAILURE_COUNTER.inc();
+                    warn!("Bulk request failed, will retry: {}", e);
+                }
+                Err(SendError::Fatal(e)) => {
+                    HTTP_FAILURE_COUNTER.inc();
+                    error!("Bulk request failed: {}", e);
+                    break;
+                }
+            }
+        }
+        error!("Dropping {} documents", docs.len());
+        dropped += docs.len();
+        HTTP_DROPPED_COUNTER.inc_by(dropped as u64);
+        dropped
+    }
+}
+
+// positions of the items to retry, and the number that failed for good
+fn rejected_items(resp: &Value) -> (Vec<usize>, usize) {
+    let mut retry = Vec::new();
+    let mut failed = 0;
+    if resp.get("errors").and_then(Value::as_bool) != Some(true) {
+        return (retry, failed);
+    }
+    let items = resp.get("items").and_then(Value::as_array);
+    for (i, item) in items.into_iter().flatten().enumerate() {
+        // each item is keyed by its action
+        let status = item
+            .as_object()
+            .and_then(|o| o.values().next())
+            .and_then(|r| r.get("status"))
+            .and_then(Value::as_u64)
+            .unwrap_or(0);
+        if status == 429 {
+            retry.push(i);
+        } else if !(200..300).contains(&status) {
+            failed += 1;
+        }
+    }
+    (retry, failed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Read;
+    use std::sync::mpsc;
+
+    fn options(url: String) -> HttpOptions {
+        HttpOptions {
+            url,
+            index: "varnish-%Y.%m.%d".into(),
+            action: BulkAction::Index,
+            batch_size: 10,
+            batch_age: Duration::from_secs(1),
+            gzip: true,
+            auth: HttpAuth::Basic {
+                username: "Aladdin".into(),
+                password: "open sesame".into(),
+            },
+            timeout: Duration::from_secs(5),
+            max_retries: 3,
+            retry_backoff: Duration::from_millis(1),
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let opts = options("http://localhost:9200".into());
+        assert!(opts.validate().is_ok());
+        for bad in [
+            HttpOptions {
+                index: "varnish-%Q".into(),
+                ..opts.clone()
+            },
+            HttpOptions {
+                batch_size: 0,
+                ..opts.clone()
+            },
+            HttpOptions {
+                batch_age: Duration::ZERO,
+                ..opts.clone()
+            },
+        ] {
+            assert!(bad.validate().is_err());
+        }
+    }
+
+    #[test]
+    fn test_bulk_retries() {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let port = server.server_addr().to_ip().unwrap().port();
+        let (tx, rx) = mpsc::channel();
+        let responses = [
+            (503, r#"{"error":"unavailable"}"#.to_string()),
+            (
+                200,
+                r#"{"errors":true,"items":[{"index":{"status":201}},{"index":{"status":429}},{"index":{"status":400,"error":{}}}]}"#.to_string(),
+            ),
+            (200, r#"{"errors":false,"items":[{"index":{"status":201}}]}"#.to_string()),
+        ];
+        let handle = std::thread::spawn(move || {
+            for (status, body) in responses {
+                let mut rq = server.recv().unwrap();
+                let auth = rq
+                    .headers()
+                    .iter()
+                    .find(|h| h.field.equiv("Authorization"))
+                    .map(|h| h.value.to_string());
+                let mut text = String::new();
+                flate2::read::GzDecoder::new(rq.as_reader())
+                    .read_to_string(&mut text)
+                    .unwrap();
+                tx.send((rq.url().to_string(), auth, text)).unwrap();
+                rq.respond(tiny_http::Response::from_string(body).with_status_code(status))
+                    .unwrap();
+            }
+        });
+
+        let sender = BulkSender::new(options(format!("http://127.0.0.1:{}/", port)));
+        let docs = vec![
+            ("varnish-a".to_string(), r#"{"n":0}"#.to_string()),
+            ("varnish-a".to_string(), r#"{"n":1}"#.to_string()),
+            ("varnish-b".to_string(), r#"{"n":2}"#.to_string()),
+        ];
+        assert_eq!(sender.send(docs), 1);
+        handle.join().unwrap();
+
+        let requests: Vec<_> = rx.iter().collect();
+        assert_eq!(requests.len(), 3);
+        let (url, auth, body) = &requests[0];
+        assert_eq!(url, "/_bulk");
+        assert_eq!(auth.as_deref(), Some("Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ=="));
+        assert_eq!(
+            body,
+            "{\"index\":{\"_index\":\"varnish-a\"}}\n{\"n\":0}\n{\"index\":{\"_index\":\"varnish-a\"}}\n{\"n\":1}\n{\"index\":{\"_index\":\"varnish-b\"}}\n{\"n\":2}\n"
+        );
+        assert_eq!(requests[1].2, requests[0].2);
+        // only the document that got a 429 is sent again
+        assert_eq!(
+            requests[2].2,
+            "{\"index\":{\"_index\":\"varnish-a\"}}\n{\"n\":1}\n"
+        );
+    }
+}
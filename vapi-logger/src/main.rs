@@ -17,8 +17,11 @@ use std::{path::PathBuf, time::Duration};
 use tracing_subscriber::filter::EnvFilter;
 
 mod config;
+mod file;
+mod http;
 pub(crate) mod metrics;
 mod output;
+mod schema;
 mod transform;
 
 #[derive(Debug, StructOpt)]
@@ -42,8 +45,16 @@ fn main() -> Result<()> {
         .unwrap();
     let opt = Opt::from_args();
     let config = load_config(&opt.config)?;
+    let formatter = config::formatter_from_config(&config.output)?;
+    let log_transform = config::transform_from_config(&config.logging, formatter.ncsa())?;
+    let log_capture = config
+        .capture
+        .as_ref()
+        .map(config::capture_from_config)
+        .transpose()?;
     let m = metrics::Metrics::new("vapi_logger");
     let metrics_config = config.metrics;
+    let stats_input = config.input.clone();
 
     thread::scope(move |s| {
         let (_tx, rx) = unbounded::<()>();
@@ -67,13 +78,28 @@ fn main() -> Result<()> {
                 }
             }
         });
-        let (log_tx, log_rx) = bounded::<LogRecord>(1000);
-        let log_transform = config::transform_from_config(&config.logging);
+        let (log_tx, mut log_rx) = bounded::<LogRecord>(1000);
+        // untouched client addresses go to their own file on the way to the output
+        let original_ip_path = config
+            .logging
+            .ip_anonymization
+            .as_ref()
+            .and_then(|a| a.original_ip_path.clone());
+        if let Some(path) = original_ip_path {
+            let (tee_tx, tee_rx) = bounded::<LogRecord>(1000);
+            let records = std::mem::replace(&mut log_rx, tee_rx);
+            s.spawn(move |_| {
+                if let Err(e) = transform::route_original_ips(records, tee_tx, &path) {
+                    error!("Original IP output failure: {}", e);
+                    std::process::exit(1);
+                }
+            });
+        }
 
         let log_query = config.logging.query.clone();
         let output_config = config.output;
         let log_consumer = s.spawn(move |_| {
-            transform::consume_logs_forever(&output_config, log_rx);
+            transform::consume_logs_forever(&output_config.destination, &formatter, log_rx);
         });
         let input_config = config.input;
         let logging_config = config.logging;
@@ -108,15 +134,18 @@ fn main() -> Result<()> {
                 .map(|&t| t.into())
                 .collect();
 
-            let res = varnish
+            let mut log_builder = varnish
                 .log_builder()
                 .query(&log_query)
                 .opts(opts)
                 .grouping(logging_config.grouping)
                 .type_filter(type_filter)
                 .reason_filter(reason_filter)
-                .reacquire_and_signal_after_overrun(tx_reacquired)
-                .start(log_tx, Some(rx), log_transform);
+                .reacquire_and_signal_after_overrun(tx_reacquired);
+            if let Some(capture) = log_capture {
+                log_builder = log_builder.capture(capture);
+            }
+            let res = log_builder.start(log_tx, Some(rx), log_transform);
             if let Err(ref e) = res {
                 error!("Varnish logging failed: {}", e);
             }
@@ -135,17 +164,31 @@ fn main() -> Result<()> {
                 for _ in 0..metrics_config.worker_threads {
                     let server = server.clone();
                     let m = m.clone();
-                    s.spawn(move |_| loop {
-                        let rq = server.recv().unwrap();
-                        if *rq.method() == tiny_http::Method::Get && rq.url() == "/metrics" {
-                            let text = m.get_metrics_text();
-                            let response =
-                                tiny_http::Response::from_string(text).with_status_code(200);
-                            let _ = rq.respond(response);
+                    let stats_enabled = metrics_config.varnish_stats;
+                    let stats_path = stats_input.path.clone();
+                    let stats_timeout = Duration::from_secs(stats_input.connect_timeout_secs);
+                    s.spawn(move |_| {
+                        let mut varnish_stats = if stats_enabled {
+                            Some(metrics::VarnishStats::new(stats_path, stats_timeout))
                         } else {
-                            let response =
-                                tiny_http::Response::from_string("Not Found").with_status_code(404);
-                            let _ = rq.respond(response);
+                            None
+                        };
+                        loop {
+                            let rq = server.recv().unwrap();
+                            if *rq.method() == tiny_http::Method::Get && rq.url() == "/metrics" {
+                                let extra = varnish_stats
+                                    .as_mut()
+                                    .map(|v| v.metric_families())
+                                    .unwrap_or_default();
+                                let text = m.get_metrics_text(extra);
+                                let response =
+                                    tiny_http::Response::from_string(text).with_status_code(200);
+                                let _ = rq.respond(response);
+                            } else {
+                                let response = tiny_http::Response::from_string("Not Found")
+                                    .with_status_code(404);
+                                let _ = rq.respond(response);
+                            }
                         }
                     });
                 }
@@ -1,5 +1,12 @@
 use lazy_static::lazy_static;
+use prometheus::proto::{LabelPair, Metric, MetricFamily, MetricType};
 use prometheus::{Encoder, Histogram, HistogramOpts, IntCounter, Registry};
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tracing::{error, info};
+use vapi::vsc::{Counter, Semantics};
+use vapi::vsm_status::{VSM_MGT_RESTARTED, VSM_WRK_RESTARTED};
+use vapi::Varnish;
 
 lazy_static! {
     pub static ref SENT_COUNTER: IntCounter =
@@ -16,6 +23,23 @@ lazy_static! {
         "count of reconnections to output destination"
     )
     .unwrap();
+    pub static ref ROTATE_COUNTER: IntCounter =
+        IntCounter::new("file_rotate_count", "count of output file rotations").unwrap();
+    pub static ref HTTP_FAILURE_COUNTER: IntCounter = IntCounter::new(
+        "http_failure_count",
+        "count of failed bulk requests to the http output"
+    )
+    .unwrap();
+    pub static ref HTTP_RETRY_COUNTER: IntCounter = IntCounter::new(
+        "http_retry_count",
+        "count of retried bulk requests to the http output"
+    )
+    .unwrap();
+    pub static ref HTTP_DROPPED_COUNTER: IntCounter = IntCounter::new(
+        "http_dropped_count",
+        "logs rejected by or never delivered to the http output"
+    )
+    .unwrap();
 }
 #[derive(Debug, Clone)]
 pub struct Metrics {
@@ -33,21 +57,307 @@ impl Metrics {
         registry
             .register(Box::new(RECONNECT_COUNTER.clone()))
             .unwrap();
+        registry.register(Box::new(ROTATE_COUNTER.clone())).unwrap();
+        registry
+            .register(Box::new(HTTP_FAILURE_COUNTER.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(HTTP_RETRY_COUNTER.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(HTTP_DROPPED_COUNTER.clone()))
+            .unwrap();
         Metrics { registry }
     }
 
     #[allow(unused)]
     pub fn print_metrics(&self) {
-        println!("{}", self.get_metrics_text());
+        println!("{}", self.get_metrics_text(Vec::new()));
     }
 
-    pub fn get_metrics_text(&self) -> String {
+    pub fn get_metrics_text(&self, extra: Vec<MetricFamily>) -> String {
         let mut buffer = Vec::<u8>::new();
         let encoder = prometheus::TextEncoder::new();
-        encoder
-            .encode(&self.registry.gather(), &mut buffer)
-            .unwrap();
+        let mut families = self.registry.gather();
+        families.extend(extra);
+        encoder.encode(&families, &mut buffer).unwrap();
 
         String::from_utf8(buffer).unwrap()
     }
 }
+
+pub struct VarnishStats {
+    path: Option<String>,
+    timeout: Duration,
+    varnish: Option<Varnish>,
+}
+
+impl VarnishStats {
+    pub fn new(path: Option<String>, timeout: Duration) -> VarnishStats {
+        VarnishStats {
+            path,
+            timeout,
+            varnish: None,
+        }
+    }
+
+    fn connect(&self) -> vapi::error::Result<Varnish> {
+        let mut builder = Varnish::builder();
+        builder.timeout(self.timeout);
+        if let Some(path) = &self.path {
+            builder.path(path);
+        }
+        builder.build()
+    }
+
+    pub fn metric_families(&mut self) -> Vec<MetricFamily> {
+        // the status call is what refreshes the segment list, so counters of backends
+        // added by a VCL reload show up. Segments of a restarted varnishd need a new handle
+        if let Some(varnish) = &self.varnish {
+            if varnish.status() & (VSM_MGT_RESTARTED | VSM_WRK_RESTARTED) != 0 {
+                info!("Varnish restarted, reattaching for stats");
+                self.varnish = None;
+            }
+        }
+        if self.varnish.is_none() {
+            match self.connect() {
+                Ok(v) => self.varnish = Some(v),
+                Err(e) => {
+                    error!("Couldn't connect to varnish for stats: {}", e);
+                    return Vec::new();
+                }
+            }
+        }
+        let snapshot = self
+            .varnish
+            .as_ref()
+            .unwrap()
+            .stats()
+            .and_then(|mut s| s.snapshot());
+        match snapshot {
+            Ok(s) => varnish_metric_families(&s),
+            Err(e) => {
+                error!("Couldn't read varnish stats: {}", e);
+                self.varnish = None;
+                Vec::new()
+            }
+        }
+    }
+}
+
+fn sanitize_metric_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Splits a dotted VSC name like `VBE.boot.default.req` into a Prometheus
+/// metric name and the labels encoded in the middle segments.
+fn vsc_metric_name(counter: &str) -> (String, Labels) {
+    let parts: Vec<&str> = counter.split('.').collect();
+    if parts.len() < 3 {
+        return (
+            format!("varnish_{}", sanitize_metric_name(counter)),
+            Vec::new(),
+        );
+    }
+    let section = parts[0];
+    let field = parts[parts.len() - 1];
+    let ident = parts[1..parts.len() - 1].join(".");
+    match section {
+        "VBE" => {
+            let (vcl, backend) = match ident.split_once('.') {
+                Some((vcl, backend)) => (vcl.to_string(), backend.to_string()),
+                None => (String::new(), ident),
+            };
+            (
+                format!("varnish_backend_{}", sanitize_metric_name(field)),
+                vec![("backend", backend), ("vcl", vcl)],
+            )
+        }
+        _ => {
+            let label = match section {
+                "SMA" | "SMF" | "SMU" | "MSE" | "MSE_STORE" | "MSE_BOOK" => "storage",
+                "LCK" => "lock",
+                "MEMPOOL" => "pool",
+                _ => "id",
+            };
+            (
+                format!(
+                    "varnish_{}_{}",
+                    sanitize_metric_name(section),
+                    sanitize_metric_name(field)
+                ),
+                vec![(label, ident)],
+            )
+        }
+    }
+}
+
+// `VBE.req` for `VBE.boot.default.req`, what all counters of a family are documented as
+fn vsc_doc_key(counter: &str) -> String {
+    match (counter.split_once('.'), counter.rsplit_once('.')) {
+        (Some((section, _)), Some((_, field))) => format!("{}.{}", section, field),
+        _ => counter.to_string(),
+    }
+}
+
+type Labels = Vec<(&'static str, String)>;
+
+// HELP and TYPE are shared by the whole family, so they're only taken from its
+// counters when they all agree
+fn metric_family(name: String, counters: Vec<(&Counter, Labels)>) -> MetricFamily {
+    let first = counters[0].0;
+    let is_counter = counters
+        .iter()
+        .all(|(c, _)| c.semantics == Semantics::Counter);
+    let help = if counters
+        .iter()
+        .all(|(c, _)| c.description == first.description)
+    {
+        first.description.clone()
+    } else {
+        vsc_doc_key(&first.name)
+    };
+    let mut family = MetricFamily::new();
+    family.set_name(name);
+    family.set_help(help);
+    family.set_field_type(if is_counter {
+        MetricType::COUNTER
+    } else {
+        MetricType::GAUGE
+    });
+    for (counter, labels) in counters {
+        let mut metric = Metric::new();
+        metric.set_label(
+            labels
+                .into_iter()
+                .map(|(k, v)| {
+                    let mut l = LabelPair::new();
+                    l.set_name(k.to_string());
+                    l.set_value(v);
+                    l
+                })
+                .collect(),
+        );
+        if is_counter {
+            let mut c = prometheus::proto::Counter::new();
+            c.set_value(counter.value as f64);
+            metric.set_counter(c);
+        } else {
+            let mut g = prometheus::proto::Gauge::new();
+            g.set_value(counter.value as f64);
+            metric.set_gauge(g);
+        }
+        family.mut_metric().push(metric);
+    }
+    family
+}
+
+pub fn varnish_metric_families<'a>(
+    counters: impl IntoIterator<Item = &'a Counter>,
+) -> Vec<MetricFamily> {
+    let mut families: BTreeMap<String, Vec<(&Counter, Labels)>> = BTreeMap::new();
+    for counter in counters {
+        let (name, labels) = vsc_metric_name(&counter.name);
+        families.entry(name).or_default().push((counter, labels));
+    }
+    families
+        .into_iter()
+        .map(|(name, counters)| metric_family(name, counters))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_vsc_metric_name() {
+        let test_cases = vec![
+            ("MAIN.cache_hit", "varnish_main_cache_hit", vec![]),
+            ("MGT.uptime", "varnish_mgt_uptime", vec![]),
+            (
+                "VBE.boot.default.req",
+                "varnish_backend_req",
+                vec![("backend", "default"), ("vcl", "boot")],
+            ),
+            (
+                "VBE.boot.goto.00000000.(10.0.0.1).(http://a).bereq_hdrbytes",
+                "varnish_backend_bereq_hdrbytes",
+                vec![
+                    ("backend", "goto.00000000.(10.0.0.1).(http://a)"),
+                    ("vcl", "boot"),
+                ],
+            ),
+            (
+                "SMA.s0.c_bytes",
+                "varnish_sma_c_bytes",
+                vec![("storage", "s0")],
+            ),
+            ("LCK.sma.creat", "varnish_lck_creat", vec![("lock", "sma")]),
+            (
+                "MEMPOOL.req0.live",
+                "varnish_mempool_live",
+                vec![("pool", "req0")],
+            ),
+            (
+                "KVSTORE.vcl.x.n",
+                "varnish_kvstore_n",
+                vec![("id", "vcl.x")],
+            ),
+        ];
+        for (input, name, labels) in test_cases {
+            let (n, l) = vsc_metric_name(input);
+            assert_eq!(n, name);
+            let l: Vec<(&str, &str)> = l.iter().map(|(k, v)| (*k, v.as_str())).collect();
+            assert_eq!(l, labels);
+        }
+    }
+
+    #[test]
+    fn test_metric_family_help() {
+        let counter = |name: &str, semantics, description: &str| Counter {
+            name: name.into(),
+            ty: "MAIN".into(),
+            semantics,
+            format: vapi::vsc::Format::Integer,
+            level: None,
+            description: description.into(),
+            long_description: String::new(),
+            value: 1,
+        };
+        let counters = vec![
+            counter(
+                "VBE.boot.a.req",
+                Semantics::Counter,
+                "Backend requests sent",
+            ),
+            counter(
+                "VBE.boot.b.req",
+                Semantics::Counter,
+                "Backend requests sent",
+            ),
+            counter("LCK.sma.locks", Semantics::Counter, "Lock Operations"),
+            counter("LCK.vbe.locks", Semantics::Gauge, "Something else"),
+        ];
+        let families = varnish_metric_families(&counters);
+        assert_eq!(families.len(), 2);
+        let backend = &families[0];
+        assert_eq!(backend.name(), "varnish_backend_req");
+        assert_eq!(backend.help(), "Backend requests sent");
+        assert_eq!(backend.get_field_type(), MetricType::COUNTER);
+        assert_eq!(backend.get_metric().len(), 2);
+        // neither lock counter decides HELP and TYPE for the other
+        let lock = &families[1];
+        assert_eq!(lock.name(), "varnish_lck_locks");
+        assert_eq!(lock.help(), "LCK.locks");
+        assert_eq!(lock.get_field_type(), MetricType::GAUGE);
+    }
+}
@@ -1,5 +1,9 @@
 use serde::Serialize;
 use serde_json::Value;
+use vapi::vsl::ncsa::NcsaFormat;
+use vapi::vsl::LogRecord;
+
+use crate::schema::{lookup, Schema};
 
 #[allow(unused)]
 #[derive(Debug, Serialize)]
@@ -11,3 +15,207 @@ pub struct LogOutput {
     pub reason: String,
     pub data: Value,
 }
+
+/// Columns are dotted paths into the record as it's serialized to JSON, like
+/// `request.url` or `timings.Resp.since_start`, with numbers indexing into lists.
+#[derive(Debug)]
+pub enum LineFormat {
+    Json,
+    Pretty,
+    /// Every field of the record if `columns` is empty
+    Logfmt(Vec<String>),
+    Delimited {
+        delimiter: char,
+        columns: Vec<String>,
+    },
+    Ncsa(NcsaFormat),
+}
+
+/// Renders records as lines for the output, without the trailing newline.
+/// The schema, if any, reshapes the record before it's rendered by any format but ncsa.
+#[derive(Debug)]
+pub struct Formatter {
+    pub format: LineFormat,
+    pub schema: Option<Schema>,
+}
+
+impl Formatter {
+    pub fn ncsa(&self) -> Option<&NcsaFormat> {
+        match &self.format {
+            LineFormat::Ncsa(f) => Some(f),
+            _ => None,
+        }
+    }
+
+    pub fn format(&self, log: &LogRecord) -> serde_json::Result<String> {
+        if let LineFormat::Ncsa(f) = &self.format {
+            return Ok(f.format(log));
+        }
+        match &self.schema {
+            Some(schema) => self.format_value(schema.apply(serde_json::to_value(log)?)),
+            None => match &self.format {
+                LineFormat::Json => serde_json::to_string(log),
+                LineFormat::Pretty => serde_json::to_string_pretty(log),
+                _ => self.format_value(serde_json::to_value(log)?),
+            },
+        }
+    }
+
+    fn format_value(&self, value: Value) -> serde_json::Result<String> {
+        match &self.format {
+            LineFormat::Json => serde_json::to_string(&value),
+            LineFormat::Pretty => serde_json::to_string_pretty(&value),
+            LineFormat::Logfmt(columns) => {
+                let mut fields = Vec::new();
+                if columns.is_empty() {
+                    flatten("", &value, &mut fields);
+                } else {
+                    for c in columns {
+                        match lookup(&value, c) {
+                            None | Some(Value::Null) => {}
+                            Some(v) => fields.push((c.clone(), scalar(v))),
+                        }
+                    }
+                }
+                Ok(logfmt(&fields))
+            }
+            LineFormat::Delimited { delimiter, columns } => {
+                let mut line = String::new();
+                for (i, c) in columns.iter().enumerate() {
+                    if i > 0 {
+                        line.push(*delimiter);
+                    }
+                    let field = lookup(&value, c).map(scalar).unwrap_or_default();
+                    if *delimiter == '\t' {
+                        push_tsv(&mut line, &field);
+                    } else {
+                        push_csv(&mut line, &field, *delimiter);
+                    }
+                }
+                Ok(line)
+            }
+            LineFormat::Ncsa(_) => unreachable!("ncsa lines are rendered from the record"),
+        }
+    }
+}
+
+// strings as they are, objects and lists as JSON
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        v => v.to_string(),
+    }
+}
+
+fn flatten(prefix: &str, value: &Value, out: &mut Vec<(String, String)>) {
+    let key = |k: &str| {
+        if prefix.is_empty() {
+            k.to_string()
+        } else {
+            format!("{}.{}", prefix, k)
+        }
+    };
+    match value {
+        Value::Object(m) => {
+            for (k, v) in m {
+                flatten(&key(k), v, out);
+            }
+        }
+        Value::Array(a) => {
+            for (i, v) in a.iter().enumerate() {
+                flatten(&key(&i.to_string()), v, out);
+            }
+        }
+        Value::Null => {}
+        v => out.push((prefix.to_string(), scalar(v))),
+    }
+}
+
+fn logfmt(fields: &[(String, String)]) -> String {
+    let mut line = String::new();
+    for (k, v) in fields {
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        // keys come from header names and VCL, keep them to something logfmt can parse
+        line.extend(k.chars().map(|c| {
+            if c.is_whitespace() || c == '=' || c == '"' {
+                '_'
+            } else {
+                c
+            }
+        }));
+        line.push('=');
+        let quote = v.is_empty()
+            || v.chars()
+                .any(|c| c == ' ' || c == '=' || c == '"' || c.is_control());
+        if !quote {
+            line.push_str(v);
+            continue;
+        }
+        line.push('"');
+        for c in v.chars() {
+            match c {
+                '"' => line.push_str("\\\""),
+                '\\' => line.push_str("\\\\"),
+                '\n' => line.push_str("\\n"),
+                '\r' => line.push_str("\\r"),
+                '\t' => line.push_str("\\t"),
+                c => line.push(c),
+            }
+        }
+        line.push('"');
+    }
+    line
+}
+
+fn push_csv(line: &mut String, field: &str, delimiter: char) {
+    if field.contains([delimiter, '"', '\n', '\r']) {
+        line.push('"');
+        line.push_str(&field.replace('"', "\"\""));
+        line.push('"');
+    } else {
+        line.push_str(field);
+    }
+}
+
+// TSV has no quoting, so escape the characters that would break a row
+fn push_tsv(line: &mut String, field: &str) {
+    for c in field.chars() {
+        match c {
+            '\\' => line.push_str("\\\\"),
+            '\t' => line.push_str("\\t"),
+            '\n' => line.push_str("\\n"),
+            '\r' => line.push_str("\\r"),
+            c => line.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_line_formats() {
+        let v = json!({"a": {"b": "x y", "c": 1}, "d": [true], "e": null, "f": "say \"hi\""});
+        let mut fields = Vec::new();
+        flatten("", &v, &mut fields);
+        assert_eq!(
+            logfmt(&fields),
+            r#"a.b="x y" a.c=1 d.0=true f="say \"hi\"""#
+        );
+
+        let mut line = String::new();
+        push_csv(&mut line, "a,b \"c\"", ',');
+        line.push(',');
+        push_csv(&mut line, "plain", ',');
+        assert_eq!(line, r#""a,b ""c""",plain"#);
+
+        let mut line = String::new();
+        push_tsv(&mut line, "a\tb\nc");
+        assert_eq!(line, "a\\tb\\nc");
+    }
+}
@@ -0,0 +1,190 @@
+use serde_json::{Map, Value};
+
+/// Reshapes a serialized `LogRecord`, paths are dotted like the output columns.
+///
+/// Mapped fields are moved to their output names, so unless `keep_unmapped` is off
+/// the rest of the record stays where it was. Dotted output names make nested
+/// objects, or with `flatten` every nested field ends up as a dotted top level key.
+#[derive(Debug, Clone)]
+pub struct Schema {
+    pub fields: Vec<(String, String)>,
+    pub keep_unmapped: bool,
+    pub drop: Vec<String>,
+    pub constants: Vec<(String, Value)>,
+    pub flatten: bool,
+}
+
+/// The field at a dotted `path`, numbers index into lists.
+pub fn lookup<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.').try_fold(value, |v, key| match v {
+        Value::Object(m) => m.get(key),
+        Value::Array(a) => a.get(key.parse::<usize>().ok()?),
+        _ => None,
+    })
+}
+
+// removes the field at `path` and any objects left empty by that
+fn remove(value: &mut Value, path: &str) {
+    let (key, rest) = match path.split_once('.') {
+        Some((key, rest)) => (key, Some(rest)),
+        None => (path, None),
+    };
+    if let Value::Object(m) = value {
+        match rest {
+            None => {
+                m.remove(key);
+            }
+            Some(rest) => {
+                if let Some(child) = m.get_mut(key) {
+                    remove(child, rest);
+                    if child.as_object().is_some_and(|c| c.is_empty()) {
+                        m.remove(key);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn insert(map: &mut Map<String, Value>, name: &str, value: Value) {
+    match name.split_once('.') {
+        None => {
+            map.insert(name.to_string(), value);
+        }
+        Some((key, rest)) => {
+            let child = map.entry(key).or_insert_with(|| Value::Object(Map::new()));
+            if !child.is_object() {
+                *child = Value::Object(Map::new());
+            }
+            if let Value::Object(child) = child {
+                insert(child, rest, value);
+            }
+        }
+    }
+}
+
+fn flatten(prefix: &str, value: Value, out: &mut Map<String, Value>) {
+    match value {
+        Value::Object(m) if !m.is_empty() => {
+            for (k, v) in m {
+                let key = if prefix.is_empty() {
+                    k
+                } else {
+                    format!("{}.{}", prefix, k)
+                };
+                flatten(&key, v, out);
+            }
+        }
+        v => {
+            out.insert(prefix.to_string(), v);
+        }
+    }
+}
+
+impl Schema {
+    pub fn apply(&self, mut record: Value) -> Value {
+        for path in &self.drop {
+            remove(&mut record, path);
+        }
+        let mapped: Vec<(&str, Value)> = self
+            .fields
+            .iter()
+            .filter_map(|(name, source)| {
+                lookup(&record, source).map(|v| (name.as_str(), v.clone()))
+            })
+            .collect();
+        if self.keep_unmapped {
+            for (_, source) in &self.fields {
+                remove(&mut record, source);
+            }
+        }
+        let mut out = match record {
+            Value::Object(m) if self.keep_unmapped => m,
+            _ => Map::new(),
+        };
+        for (name, value) in mapped {
+            insert(&mut out, name, value);
+        }
+        for (name, value) in &self.constants {
+            insert(&mut out, name, value.clone());
+        }
+        if self.flatten {
+            let mut flat = Map::new();
+            flatten("", Value::Object(out), &mut flat);
+            out = flat;
+        }
+        Value::Object(out)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn record() -> Value {
+        json!({
+            "vxid": 5,
+            "call_chain": ["Call recv"],
+            "request": {"url": "/a", "method": "GET", "remoteip": "192.0.2.1"},
+            "response": {"status": 200},
+        })
+    }
+
+    fn schema() -> Schema {
+        Schema {
+            fields: vec![
+                ("url.original".into(), "request.url".into()),
+                ("source.ip".into(), "request.remoteip".into()),
+                ("http.response.status_code".into(), "response.status".into()),
+                ("missing".into(), "request.nope".into()),
+            ],
+            keep_unmapped: true,
+            drop: vec!["call_chain".into()],
+            constants: vec![("event.dataset".into(), json!("varnish.access"))],
+            flatten: false,
+        }
+    }
+
+    #[test]
+    fn test_lookup() {
+        let v = record();
+        assert_eq!(lookup(&v, "request.url"), Some(&json!("/a")));
+        assert_eq!(lookup(&v, "call_chain.0"), Some(&json!("Call recv")));
+        assert_eq!(lookup(&v, "request.url.x"), None);
+        assert_eq!(lookup(&v, "timings"), None);
+    }
+
+    #[test]
+    fn test_schema_rename() {
+        assert_eq!(
+            schema().apply(record()),
+            json!({
+                "vxid": 5,
+                "request": {"method": "GET"},
+                "url": {"original": "/a"},
+                "source": {"ip": "192.0.2.1"},
+                "http": {"response": {"status_code": 200}},
+                "event": {"dataset": "varnish.access"},
+            })
+        );
+    }
+
+    #[test]
+    fn test_schema_only_mapped_flat() {
+        let s = Schema {
+            keep_unmapped: false,
+            flatten: true,
+            ..schema()
+        };
+        assert_eq!(
+            s.apply(record()),
+            json!({
+                "url.original": "/a",
+                "source.ip": "192.0.2.1",
+                "http.response.status_code": 200,
+                "event.dataset": "varnish.access",
+            })
+        );
+    }
+}
@@ -1,15 +1,21 @@
-use crate::config::OutputConfig;
+use crate::config::{http_from_config, Destination};
+use crate::file::{Compression, FileOptions, RotatingFile};
+use crate::http::{BulkSender, HttpOptions};
 use crate::metrics::{RECONNECT_COUNTER, SENT_COUNTER, SENT_HISTO};
-use anyhow::{anyhow, Result};
+use crate::output::Formatter;
+use anyhow::{anyhow, Context, Result};
 use crossbeam::select;
-use crossbeam_channel::Receiver;
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
 use std::io::prelude::*;
 use std::net::TcpStream;
-use std::time::Duration;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use std::{net::SocketAddr, net::ToSocketAddrs};
 use tracing::{error, info};
 use vapi::vsl::LogRecord;
-fn send_to_stdout(rx: Receiver<LogRecord>) -> Result<()> {
+fn send_to_stdout(rx: Receiver<LogRecord>, formatter: &Formatter) -> Result<()> {
     loop {
         select! {
             recv(rx) -> res => {
@@ -21,9 +27,15 @@ fn send_to_stdout(rx: Receiver<LogRecord>) -> Result<()> {
                     },
                 };
                 SENT_COUNTER.inc();
+                let json = match formatter.format(&log) {
+                    Ok(j) => j,
+                    Err(e) => {
+                        error!("Couldn't transform struct: {}", e);
+                        continue;
+                    },
+                };
                 let timer = SENT_HISTO.start_timer();
-                println!("-----------------------------------------");
-                println!("{}", serde_json::to_string_pretty(&log).unwrap());
+                println!("{}", json);
                 timer.observe_duration();
             }
         }
@@ -51,6 +63,7 @@ fn loop_until_connected(
 
 fn send_to_tcp(
     rx: Receiver<LogRecord>,
+    formatter: &Formatter,
     host: &str,
     port: u16,
     timeout: u64,
@@ -80,7 +93,7 @@ fn send_to_tcp(
                                 },
                             };
                             SENT_COUNTER.inc();
-                            let mut json: String = match serde_json::to_string(&log) {
+                            let mut json: String = match formatter.format(&log) {
                                 Ok(j) => j,
                                 Err(e) => {
                                     error!("Couldn't transform struct: {}", e);
@@ -110,6 +123,155 @@ fn send_to_tcp(
     Ok(())
 }
 
+fn send_to_file(rx: Receiver<LogRecord>, formatter: &Formatter, opts: FileOptions) -> Result<()> {
+    // set on SIGHUP, so logrotate can move the file away and have us start a new one
+    let reopen = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(signal_hook::consts::SIGHUP, reopen.clone())?;
+    let mut file = RotatingFile::open(opts, reopen).context("Couldn't open output file")?;
+    info!("Writing logs to {}", file.path().display());
+    loop {
+        // wake up now and then to flush, reopen, and rotate by age even when it's quiet
+        match rx.recv_timeout(Duration::from_secs(1)) {
+            Ok(log) => {
+                SENT_COUNTER.inc();
+                let mut json = match formatter.format(&log) {
+                    Ok(j) => j,
+                    Err(e) => {
+                        error!("Couldn't transform struct: {}", e);
+                        continue;
+                    }
+                };
+                json.push('\n');
+                let timer = SENT_HISTO.start_timer();
+                let res = file.write(json.as_bytes());
+                timer.observe_duration();
+                if let Err(e) = res {
+                    error!("Error writing to {}: {}", file.path().display(), e);
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                if let Err(e) = file.tick().and_then(|_| file.flush()) {
+                    error!("Error writing to {}: {}", file.path().display(), e);
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                file.finish()?;
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn send_to_http(rx: Receiver<LogRecord>, formatter: &Formatter, opts: HttpOptions) -> Result<()> {
+    info!("Sending logs to {}", opts.url);
+    let sender = BulkSender::new(opts);
+    let (batch_size, batch_age) = (sender.options().batch_size, sender.options().batch_age);
+    let mut batch = Vec::with_capacity(batch_size);
+    let mut started = Instant::now();
+    let flush = |batch: &mut Vec<(String, String)>| {
+        let timer = SENT_HISTO.start_timer();
+        sender.send(std::mem::take(batch));
+        timer.observe_duration();
+    };
+    loop {
+        let wait = if batch.is_empty() {
+            batch_age
+        } else {
+            batch_age.saturating_sub(started.elapsed())
+        };
+        match rx.recv_timeout(wait) {
+            Ok(log) => {
+                SENT_COUNTER.inc();
+                let json = match formatter.format(&log) {
+                    Ok(j) => j,
+                    Err(e) => {
+                        error!("Couldn't transform struct: {}", e);
+                        continue;
+                    }
+                };
+                if batch.is_empty() {
+                    started = Instant::now();
+                }
+                batch.push((sender.index_name(&log), json));
+                if batch.len() >= batch_size {
+                    flush(&mut batch);
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                if !batch.is_empty() {
+                    flush(&mut batch);
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                if !batch.is_empty() {
+                    flush(&mut batch);
+                }
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Write the untouched client address of every record that has one to `path`, then
+/// pass the record on to `tx`. Lines are `{"vxid", "remoteip", "original_ip"}` objects.
+pub fn route_original_ips(
+    rx: Receiver<LogRecord>,
+    tx: Sender<LogRecord>,
+    path: &str,
+) -> Result<()> {
+    let reopen = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(signal_hook::consts::SIGHUP, reopen.clone())?;
+    let opts = FileOptions {
+        path: PathBuf::from(path),
+        rotate_bytes: None,
+        rotate_interval: None,
+        compression: Compression::None,
+        keep: None,
+    };
+    let mut file = RotatingFile::open(opts, reopen).context("Couldn't open original IP file")?;
+    info!("Writing original client addresses to {}", path);
+    loop {
+        match rx.recv_timeout(Duration::from_secs(1)) {
+            Ok(log) => {
+                let mut lines = String::new();
+                original_ips(&log, &mut lines);
+                if !lines.is_empty() {
+                    if let Err(e) = file.write(lines.as_bytes()) {
+                        error!("Error writing to {}: {}", path, e);
+                    }
+                }
+                if tx.send(log).is_err() {
+                    return Ok(file.finish()?);
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                if let Err(e) = file.tick().and_then(|_| file.flush()) {
+                    error!("Error writing to {}: {}", path, e);
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                file.finish()?;
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn original_ips(log: &LogRecord, out: &mut String) {
+    if let Some(ip) = log.request.original_ip {
+        let line = serde_json::json!({
+            "vxid": log.vxid,
+            "remoteip": log.request.remoteip,
+            "original_ip": ip,
+        });
+        out.push_str(&line.to_string());
+        out.push('\n');
+    }
+    for child in &log.children {
+        original_ips(child, out);
+    }
+}
+
 fn null_consumer(rx: Receiver<LogRecord>) -> Result<()> {
     loop {
         select! {
@@ -125,10 +287,10 @@ fn null_consumer(rx: Receiver<LogRecord>) -> Result<()> {
     }
 }
 
-pub fn consume_logs_forever(output: &OutputConfig, rx: Receiver<LogRecord>) {
+pub fn consume_logs_forever(output: &Destination, formatter: &Formatter, rx: Receiver<LogRecord>) {
     let res = match output {
-        OutputConfig::Stdout => send_to_stdout(rx),
-        OutputConfig::Tcp {
+        Destination::Stdout => send_to_stdout(rx, formatter),
+        Destination::Tcp {
             host,
             port,
             connect_timeout_secs,
@@ -136,13 +298,34 @@ pub fn consume_logs_forever(output: &OutputConfig, rx: Receiver<LogRecord>) {
             sender_threads,
         } => send_to_tcp(
             rx,
+            formatter,
             host,
             *port,
             *connect_timeout_secs,
             *retry_interval_secs,
             *sender_threads,
         ),
-        OutputConfig::Null => null_consumer(rx),
+        Destination::File {
+            path,
+            rotate_bytes,
+            rotate_interval_secs,
+            compression,
+            keep,
+        } => send_to_file(
+            rx,
+            formatter,
+            FileOptions {
+                path: PathBuf::from(path),
+                rotate_bytes: *rotate_bytes,
+                rotate_interval: rotate_interval_secs.map(Duration::from_secs),
+                compression: *compression,
+                keep: *keep,
+            },
+        ),
+        Destination::Http { .. } => {
+            http_from_config(output).and_then(|opts| send_to_http(rx, formatter, opts))
+        }
+        Destination::Null => null_consumer(rx),
     };
     if let Err(e) = res {
         error!("Output failure: {}", e);
@@ -8,6 +8,8 @@ pub enum VarnishError {
     VSMError(String),
     #[error("VSL Error: {0}")]
     VSLError(String),
+    #[error("VSC Error: {0}")]
+    VSCError(String),
     #[error("Log overrun")]
     LogOverrun,
     #[error("I/O read error")]
@@ -16,6 +18,8 @@ pub enum VarnishError {
     UserStatus(i32),
     #[error("Callback Error: {0}")]
     CallbackError(String),
+    #[error("Format Error: {0}")]
+    FormatError(String),
 }
 
 impl VarnishError {
@@ -1,17 +1,22 @@
 pub mod error;
+pub mod rotate;
 pub mod vapi;
+pub mod vsc;
 pub mod vsl;
 mod vsm;
 
 pub use crate::vapi::Varnish;
+pub use crate::vsc::{Counter, Snapshot, Stats};
 pub use crate::vsl::{
-    CallbackResult, CursorOpts, LogCallback, LogGrouping, LogLine, LogTransaction, Reason,
-    RecordType, TxType,
+    tag::Tag, CallbackResult, CursorOpts, LogCallback, LogGrouping, LogLine, LogTransaction,
+    Reason, RecordType, TxType,
 };
+pub use crate::vsm::vsm_status;
 
 pub mod prelude {
     pub use crate::error::VarnishError;
-    pub use crate::vapi::{Builder, Varnish};
+    pub use crate::vapi::{Builder, LoggingBuilder, Varnish};
+    pub use crate::vsc::{Counter, Format, Semantics, Snapshot, Stats};
     pub use crate::vsl::{
         CallbackResult, CursorOpts, LogCallback, LogGrouping, LogLine, LogTransaction, Reason,
         RecordType, TxType,
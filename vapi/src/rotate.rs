@@ -0,0 +1,171 @@
+//! Naming and pruning of rotated files. A file rotated from `<path>` is renamed to
+//! `<path>.<unix secs>`, with a `-N` counter for more rotations in the same second.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The name to rotate `path` to. It sorts after every file already rotated in the same
+/// second, including ones ending with one of `extensions`, so pruning never mistakes
+/// it for an older one.
+pub fn rotated_path(path: &Path, extensions: &[&str]) -> io::Result<PathBuf> {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let last = rotated_files(path, extensions)?
+        .into_iter()
+        .filter(|((s, _), _)| *s == secs)
+        .map(|((_, n), _)| n)
+        .max();
+    Ok(PathBuf::from(match last {
+        None => format!("{}.{}", path.display(), secs),
+        Some(n) => format!("{}.{}-{}", path.display(), secs, n + 1),
+    }))
+}
+
+fn number(s: &str) -> Option<u64> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+// the (secs, counter) of a name made by `rotated_path` from `file_name`, which may
+// also end with one of `extensions`
+fn rotation_key(name: &str, file_name: &str, extensions: &[&str]) -> Option<(u64, u64)> {
+    let suffix = name.strip_prefix(file_name)?.strip_prefix('.')?;
+    let suffix = extensions
+        .iter()
+        .find_map(|ext| suffix.strip_suffix(ext)?.strip_suffix('.'))
+        .unwrap_or(suffix);
+    let (secs, n) = match suffix.split_once('-') {
+        Some((secs, n)) => (secs, number(n)?),
+        None => (suffix, 0),
+    };
+    // a timestamp, not logrotate's `.1` or `-YYYYMMDD`
+    if secs.len() < 10 {
+        return None;
+    }
+    Some((number(secs)?, n))
+}
+
+// every file rotated from `path`, with its (secs, counter)
+fn rotated_files(path: &Path, extensions: &[&str]) -> io::Result<Vec<((u64, u64), PathBuf)>> {
+    let dir = match path.parent() {
+        Some(d) if !d.as_os_str().is_empty() => d,
+        _ => Path::new("."),
+    };
+    let file_name = match path.file_name() {
+        Some(n) => n.to_string_lossy(),
+        None => return Ok(Vec::new()),
+    };
+    Ok(fs::read_dir(dir)?
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let key = rotation_key(&e.file_name().to_string_lossy(), &file_name, extensions)?;
+            Some((key, e.path()))
+        })
+        .collect())
+}
+
+/// Delete all but the newest `keep` files rotated from `path`. Other files next to it,
+/// like `<path>.bak` or `<path>.1.gz` from logrotate, are left alone.
+pub fn prune_rotated(path: &Path, keep: usize, extensions: &[&str]) -> io::Result<()> {
+    let mut rotated = rotated_files(path, extensions)?;
+    if rotated.len() <= keep {
+        return Ok(());
+    }
+    rotated.sort();
+    for (_, old) in &rotated[..rotated.len() - keep] {
+        fs::remove_file(old)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rotation_key() {
+        let ext = ["gz", "zst"];
+        assert_eq!(
+            rotation_key("cap.vsl.1700000000", "cap.vsl", &ext),
+            Some((1700000000, 0))
+        );
+        assert_eq!(
+            rotation_key("cap.vsl.1700000000-10", "cap.vsl", &ext),
+            Some((1700000000, 10))
+        );
+        assert_eq!(
+            rotation_key("cap.vsl.1700000000-2.gz", "cap.vsl", &ext),
+            Some((1700000000, 2))
+        );
+        assert_eq!(
+            rotation_key("cap.vsl.1700000000.zst", "cap.vsl", &ext),
+            Some((1700000000, 0))
+        );
+        assert_eq!(rotation_key("cap.vsl.1700000000.gz", "cap.vsl", &[]), None);
+        assert_eq!(rotation_key("cap.vsl.1.gz", "cap.vsl", &ext), None);
+        assert_eq!(rotation_key("cap.vsl.20240101", "cap.vsl", &ext), None);
+        assert_eq!(rotation_key("cap.vsl", "cap.vsl", &ext), None);
+        assert_eq!(rotation_key("cap.vsl.bak", "cap.vsl", &ext), None);
+        assert_eq!(rotation_key("cap.vsl.lock", "cap.vsl", &ext), None);
+        assert_eq!(rotation_key("cap.vsl.1.gz.tmp", "cap.vsl", &ext), None);
+        assert_eq!(rotation_key("cap.vsl.-1", "cap.vsl", &ext), None);
+        assert_eq!(rotation_key("cap.vsl.1700000000-", "cap.vsl", &ext), None);
+        assert_eq!(rotation_key("cap.vsl2.1700000000", "cap.vsl", &ext), None);
+    }
+
+    #[test]
+    fn test_prune_rotated() {
+        let dir = std::env::temp_dir().join(format!("vapi-rotate-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("cap.vsl");
+        let names = [
+            "cap.vsl",
+            "cap.vsl.1700000100",
+            "cap.vsl.1700000100-2",
+            "cap.vsl.1700000100-10",
+            "cap.vsl.1700000099-11",
+            "cap.vsl.bak",
+            "cap.vsl.lock",
+            "cap.vsl.1.gz",
+        ];
+        for name in names {
+            fs::write(dir.join(name), name).unwrap();
+        }
+        prune_rotated(&path, 2, &["gz"]).unwrap();
+        let mut left: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        left.sort();
+        assert_eq!(
+            left,
+            vec![
+                "cap.vsl",
+                "cap.vsl.1.gz",
+                "cap.vsl.1700000100-10",
+                "cap.vsl.1700000100-2",
+                "cap.vsl.bak",
+                "cap.vsl.lock"
+            ]
+        );
+
+        let rotated = rotated_path(&path, &[]).unwrap();
+        let name = rotated.file_name().unwrap().to_string_lossy().into_owned();
+        let (secs, n) = rotation_key(&name, "cap.vsl", &[]).unwrap();
+        assert_eq!(n, 0);
+        // later rotations in the same second sort after it, even if older ones are gone
+        fs::write(dir.join(format!("{}-3.gz", name)), "").unwrap();
+        let next = rotated_path(&path, &["gz"]).unwrap();
+        let next = next.file_name().unwrap().to_string_lossy().into_owned();
+        let key = rotation_key(&next, "cap.vsl", &[]).unwrap();
+        assert!(key == (secs, 4) || key.0 > secs);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
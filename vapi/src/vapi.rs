@@ -1,9 +1,16 @@
 use crate::error::Result;
+use crate::vsc::Stats;
+#[cfg(feature = "tokio")]
+use crate::vsl::async_stream::AsyncLogStream;
+use crate::vsl::capture::LogCapture;
+use crate::vsl::stream::LogStream;
 use crate::vsl::transform::LogTransform;
-use crate::vsl::{CursorOpts, LogGrouping, LogRecord, VarnishLogBuilder};
+use crate::vsl::view::TransactionView;
+use crate::vsl::{CursorOpts, LogCallback, LogGrouping, LogRecord, LogSource, VarnishLogBuilder};
 use crate::vsm::{OpenVSM, VSMBuilder};
-use crate::{Reason, TxType};
+use crate::{CallbackResult, Reason, TxType};
 use crossbeam_channel::{Receiver, Sender};
+use std::path::Path;
 use std::time::Duration;
 
 pub struct Varnish {
@@ -56,10 +63,21 @@ impl Varnish {
     pub fn log_builder(&self) -> LoggingBuilder<'_> {
         LoggingBuilder::new(&self.shm)
     }
+
+    pub fn stats(&self) -> Result<Stats<'_>> {
+        Stats::new(&self.shm)
+    }
+
+    /// The `VSM_Status` bits, see `vsm_status`. This is also what picks up shared memory
+    /// segments added since the last call, so call it before each snapshot when the
+    /// handle is kept around.
+    pub fn status(&self) -> u32 {
+        self.shm.status()
+    }
 }
 #[derive(Debug)]
 pub struct LoggingBuilder<'vsm> {
-    vsm: &'vsm OpenVSM,
+    source: LogSource<'vsm>,
     query: Option<String>,
     opts: CursorOpts,
     grouping: LogGrouping,
@@ -67,12 +85,25 @@ pub struct LoggingBuilder<'vsm> {
     reacquire_signal: Option<Sender<()>>,
     type_filter: Vec<TxType>,
     reason_filter: Vec<Reason>,
+    capture: Option<LogCapture>,
+    transform: Option<LogTransform>,
+}
+
+impl LoggingBuilder<'static> {
+    /// Read logs from a binary file written by `varnishlog -w`. A path of `-` reads from stdin.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> LoggingBuilder<'static> {
+        LoggingBuilder::with_source(LogSource::File(path.as_ref().to_path_buf()))
+    }
 }
 
 impl<'vsm> LoggingBuilder<'vsm> {
     fn new(vsm: &'vsm OpenVSM) -> LoggingBuilder<'vsm> {
+        LoggingBuilder::with_source(LogSource::Vsm(vsm))
+    }
+
+    fn with_source(source: LogSource<'vsm>) -> LoggingBuilder<'vsm> {
         LoggingBuilder {
-            vsm,
+            source,
             query: None,
             opts: CursorOpts::new(),
             grouping: LogGrouping::Vxid,
@@ -80,6 +111,8 @@ impl<'vsm> LoggingBuilder<'vsm> {
             reacquire_signal: None,
             type_filter: Vec::new(),
             reason_filter: Vec::new(),
+            capture: None,
+            transform: None,
         }
     }
 
@@ -111,6 +144,19 @@ impl<'vsm> LoggingBuilder<'vsm> {
         self
     }
 
+    /// Write every transaction that passes the query and the type and reason filters
+    /// to a varnishlog-compatible binary file, in addition to producing `LogRecord`s.
+    pub fn capture(mut self, capture: LogCapture) -> Self {
+        self.capture = Some(capture);
+        self
+    }
+
+    /// Transform used by `iter`, defaults to `LogTransform::default()`.
+    pub fn transform(mut self, transform: LogTransform) -> Self {
+        self.transform = Some(transform);
+        self
+    }
+
     pub fn reacquire_after_overrun(mut self) -> Self {
         self.reacquire = true;
         self
@@ -122,13 +168,8 @@ impl<'vsm> LoggingBuilder<'vsm> {
         self
     }
 
-    pub fn start(
-        self,
-        log_sender: Sender<LogRecord>,
-        stop_channel: Option<Receiver<()>>,
-        transform: LogTransform,
-    ) -> Result<()> {
-        let mut builder = VarnishLogBuilder::new(log_sender, transform);
+    fn into_options(self) -> (LogSource<'vsm>, VarnishLogBuilder) {
+        let mut builder = VarnishLogBuilder::new();
         builder.grouping(self.grouping);
         builder.cursor_opts(self.opts);
         if let Some(tx) = self.reacquire_signal {
@@ -141,6 +182,56 @@ impl<'vsm> LoggingBuilder<'vsm> {
         }
         builder.type_filter(self.type_filter);
         builder.reason_filter(self.reason_filter);
-        builder.execute(self.vsm, stop_channel)
+        if let Some(capture) = self.capture {
+            builder.capture(capture);
+        }
+        (self.source, builder)
+    }
+
+    pub fn start(
+        self,
+        log_sender: Sender<LogRecord>,
+        stop_channel: Option<Receiver<()>>,
+        transform: LogTransform,
+    ) -> Result<()> {
+        let (source, builder) = self.into_options();
+        builder.execute(source, log_sender, transform, stop_channel)
+    }
+
+    /// Hand every transaction to `callback` with all of its records, skipping the
+    /// transform entirely.
+    pub fn start_with_callback(
+        self,
+        callback: LogCallback,
+        stop_channel: Option<Receiver<()>>,
+    ) -> Result<()> {
+        let (source, builder) = self.into_options();
+        builder.execute_callback(source, callback, stop_channel)
+    }
+
+    /// Like `start_with_callback`, but the callback borrows each transaction's records
+    /// in place instead of receiving owned copies. The view is only valid during the call.
+    pub fn start_with_view<F>(self, callback: F, stop_channel: Option<Receiver<()>>) -> Result<()>
+    where
+        F: FnMut(&mut TransactionView) -> CallbackResult,
+    {
+        let (source, builder) = self.into_options();
+        builder.execute_view(source, callback, stop_channel)
+    }
+
+    /// Pull log records on the calling thread instead of pushing them into a channel.
+    pub fn iter(mut self) -> Result<LogStream<'vsm>> {
+        let transform = self.transform.take().unwrap_or_default();
+        let (source, builder) = self.into_options();
+        builder.stream(source, transform)
+    }
+
+    /// Consume log records as a `futures` Stream, reading happens on a dedicated
+    /// thread and at most `capacity` records are buffered. `capacity` can't be 0.
+    #[cfg(feature = "tokio")]
+    pub fn stream(mut self, capacity: usize) -> Result<AsyncLogStream> {
+        let transform = self.transform.take().unwrap_or_default();
+        let (source, builder) = self.into_options();
+        builder.stream_async(source, transform, capacity)
     }
 }
@@ -0,0 +1,207 @@
+use crate::error::{Result, VarnishError};
+use crate::vsm::OpenVSM;
+use serde::Serialize;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_void};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+pub enum Semantics {
+    Counter,
+    Gauge,
+    Bitmap,
+    Unknown,
+}
+
+impl From<c_int> for Semantics {
+    fn from(c: c_int) -> Semantics {
+        use Semantics::*;
+        match u8::try_from(c).map(char::from) {
+            Ok('c') => Counter,
+            Ok('g') => Gauge,
+            Ok('b') => Bitmap,
+            _ => Unknown,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+pub enum Format {
+    Integer,
+    Bytes,
+    Bitmap,
+    Duration,
+    Unknown,
+}
+
+impl From<c_int> for Format {
+    fn from(c: c_int) -> Format {
+        use Format::*;
+        match u8::try_from(c).map(char::from) {
+            Ok('i') => Integer,
+            Ok('B') => Bytes,
+            Ok('b') => Bitmap,
+            Ok('d') => Duration,
+            _ => Unknown,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Counter {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: String,
+    pub semantics: Semantics,
+    pub format: Format,
+    pub level: Option<String>,
+    pub description: String,
+    pub long_description: String,
+    pub value: u64,
+}
+
+/// Owned copy of every counter visible at the time `Stats::snapshot` was called.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(transparent)]
+pub struct Snapshot {
+    counters: Vec<Counter>,
+}
+
+impl Snapshot {
+    pub fn get(&self, name: &str) -> Option<&Counter> {
+        self.counters.iter().find(|c| c.name == name)
+    }
+
+    pub fn value(&self, name: &str) -> Option<u64> {
+        self.get(name).map(|c| c.value)
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Counter> {
+        self.counters.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.counters.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counters.is_empty()
+    }
+}
+
+impl IntoIterator for Snapshot {
+    type Item = Counter;
+    type IntoIter = std::vec::IntoIter<Counter>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.counters.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Snapshot {
+    type Item = &'a Counter;
+    type IntoIter = std::slice::Iter<'a, Counter>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.counters.iter()
+    }
+}
+
+#[derive(Debug)]
+pub struct Stats<'vsm> {
+    vsm: &'vsm OpenVSM,
+    vsc: *mut vapi_sys::vsc,
+}
+
+impl<'vsm> Stats<'vsm> {
+    pub(crate) fn new(vsm: &'vsm OpenVSM) -> Result<Stats<'vsm>> {
+        let vsc = unsafe { vapi_sys::VSC_New() };
+        if vsc.is_null() {
+            return Err(VarnishError::VSCError("Could not allocate VSC".into()));
+        }
+        Ok(Stats { vsm, vsc })
+    }
+
+    /// Limit snapshots to counters matching `pattern`, a glob such as `MAIN.*`.
+    /// Prefix the pattern with `^` to exclude matching counters instead.
+    pub fn include<S: Into<String>>(&mut self, pattern: S) -> Result<&mut Self> {
+        let pattern = pattern.into();
+        let val = CString::new(pattern.as_str())
+            .map_err(|_| VarnishError::VSCError(format!("Invalid pattern: {}", pattern)))?;
+        let arg = CString::new(String::from("f")).unwrap();
+        unsafe {
+            if vapi_sys::VSC_Arg(self.vsc, *arg.as_ptr(), val.as_ptr()) != 1 {
+                return Err(VarnishError::VSCError(format!(
+                    "Invalid pattern: {}",
+                    pattern
+                )));
+            }
+        }
+        Ok(self)
+    }
+
+    pub fn snapshot(&mut self) -> Result<Snapshot> {
+        let mut counters: Vec<Counter> = Vec::new();
+        let res = unsafe {
+            vapi_sys::VSC_Iter(
+                self.vsc,
+                self.vsm.0.vsm,
+                Some(collect_point),
+                &mut counters as *mut Vec<Counter> as *mut c_void,
+            )
+        };
+        if res != 0 {
+            return Err(VarnishError::VSCError(format!(
+                "Counter iteration failed: {}",
+                res
+            )));
+        }
+        Ok(Snapshot { counters })
+    }
+}
+
+impl Drop for Stats<'_> {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.vsc.is_null() {
+                vapi_sys::VSC_Destroy(&mut self.vsc, self.vsm.0.vsm);
+            }
+        }
+    }
+}
+
+unsafe fn string_from_ptr(s: *const c_char) -> String {
+    if s.is_null() {
+        String::new()
+    } else {
+        CStr::from_ptr(s).to_string_lossy().into_owned()
+    }
+}
+
+unsafe extern "C" fn collect_point(priv_: *mut c_void, pt: *const vapi_sys::VSC_point) -> c_int {
+    if pt.is_null() {
+        return 0;
+    }
+    let counters: &mut Vec<Counter> = &mut *(priv_ as *mut Vec<Counter>);
+    let pt = &*pt;
+    let value = if pt.ptr.is_null() {
+        0
+    } else {
+        std::ptr::read_volatile(pt.ptr)
+    };
+    let level = if pt.level.is_null() {
+        None
+    } else {
+        Some(string_from_ptr((*pt.level).name))
+    };
+    counters.push(Counter {
+        name: string_from_ptr(pt.name),
+        ty: string_from_ptr(pt.ctype),
+        semantics: pt.semantics.into(),
+        format: pt.format.into(),
+        level,
+        description: string_from_ptr(pt.sdesc),
+        long_description: string_from_ptr(pt.ldesc),
+        value,
+    });
+    0
+}
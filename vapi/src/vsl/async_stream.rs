@@ -0,0 +1,137 @@
+use super::internal::{LogReader, ReadStatus, RecordSink, TransformDispatch};
+use super::transform::LogTransform;
+use super::{LogRecord, LogSource, VarnishLogBuilder};
+use crate::error::{Result, VarnishError};
+use crate::vsm::VsmArgs;
+use futures_core::Stream;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tracing::error;
+
+impl RecordSink for mpsc::Sender<LogRecord> {
+    fn send(&mut self, log: LogRecord) -> bool {
+        // fails only once the stream has been dropped
+        self.blocking_send(log).is_ok()
+    }
+}
+
+// a LogSource that can be moved onto the dispatch thread
+pub(crate) enum OwnedSource {
+    Vsm(VsmArgs),
+    File(PathBuf),
+}
+
+impl From<&LogSource<'_>> for OwnedSource {
+    fn from(source: &LogSource<'_>) -> OwnedSource {
+        match source {
+            LogSource::Vsm(vsm) => OwnedSource::Vsm(vsm.1.clone()),
+            LogSource::File(path) => OwnedSource::File(path.clone()),
+        }
+    }
+}
+
+/// Asynchronous stream of log records.
+///
+/// Varnish is read on a dedicated thread that blocks whenever the stream's buffer
+/// is full, and exits once the stream is dropped.
+pub struct AsyncLogStream {
+    rx: mpsc::Receiver<LogRecord>,
+    result: oneshot::Receiver<Result<()>>,
+}
+
+impl AsyncLogStream {
+    pub(crate) fn spawn(
+        source: OwnedSource,
+        options: VarnishLogBuilder,
+        transform: LogTransform,
+        capacity: usize,
+    ) -> Result<AsyncLogStream> {
+        if capacity == 0 {
+            return Err(VarnishError::VSLError(
+                "Stream capacity has to be at least 1".into(),
+            ));
+        }
+        let (tx, rx) = mpsc::channel(capacity);
+        let (result_tx, result) = oneshot::channel();
+        std::thread::Builder::new()
+            .name("vapi-log-stream".into())
+            .spawn(move || {
+                let res = dispatch_until_closed(source, options, transform, tx);
+                if let Err(ref e) = res {
+                    error!("Log stream failed: {}", e);
+                }
+                let _ = result_tx.send(res);
+            })
+            .map_err(|e| VarnishError::VSLError(format!("Couldn't start log thread: {}", e)))?;
+        Ok(AsyncLogStream { rx, result })
+    }
+
+    /// Stop reading and wait for the dispatch thread to exit, returning the error
+    /// that ended the stream, if any.
+    pub async fn finish(self) -> Result<()> {
+        let AsyncLogStream { rx, result } = self;
+        drop(rx);
+        result
+            .await
+            .unwrap_or_else(|_| Err(VarnishError::VSLError("Log thread panicked".into())))
+    }
+}
+
+impl Stream for AsyncLogStream {
+    type Item = LogRecord;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<LogRecord>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+fn dispatch_until_closed(
+    source: OwnedSource,
+    options: VarnishLogBuilder,
+    transform: LogTransform,
+    tx: mpsc::Sender<LogRecord>,
+) -> Result<()> {
+    let vsm;
+    let source = match source {
+        OwnedSource::Vsm(args) => {
+            vsm = args.attach()?;
+            LogSource::Vsm(&vsm)
+        }
+        OwnedSource::File(path) => LogSource::File(path),
+    };
+    let handler = TransformDispatch {
+        transform,
+        sink: tx,
+    };
+    let mut reader = LogReader::new(source, options, handler)?;
+    loop {
+        let status = reader.dispatch();
+        if reader.handler_mut().sink.is_closed() {
+            return Ok(());
+        }
+        match status? {
+            ReadStatus::More => {}
+            ReadStatus::Idle => std::thread::sleep(Duration::from_millis(10)),
+            ReadStatus::Eof => return Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_zero_capacity() {
+        let res = AsyncLogStream::spawn(
+            OwnedSource::File(PathBuf::from("/nonexistent")),
+            VarnishLogBuilder::new(),
+            LogTransform::new(),
+            0,
+        );
+        assert!(matches!(res, Err(VarnishError::VSLError(_))));
+    }
+}
@@ -0,0 +1,201 @@
+use crate::error::{Result, VarnishError};
+use crate::rotate::{prune_rotated, rotated_path};
+use crate::vsl::{Reason, TxType};
+use std::ffi::{CStr, CString};
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
+
+#[derive(Debug, Clone)]
+pub struct LogCapture {
+    path: PathBuf,
+    max_bytes: Option<u64>,
+    max_age: Option<Duration>,
+    keep: Option<usize>,
+}
+
+impl LogCapture {
+    pub fn new<P: AsRef<Path>>(path: P) -> LogCapture {
+        LogCapture {
+            path: path.as_ref().to_path_buf(),
+            max_bytes: None,
+            max_age: None,
+            keep: None,
+        }
+    }
+
+    /// Rotate once the file grows past `bytes`, 0 is ignored.
+    pub fn rotate_size(mut self, bytes: u64) -> Self {
+        self.max_bytes = Some(bytes).filter(|&b| b > 0);
+        self
+    }
+
+    /// Rotate once the file is `interval` old, a zero interval is ignored.
+    pub fn rotate_interval(mut self, interval: Duration) -> Self {
+        self.max_age = Some(interval).filter(|i| !i.is_zero());
+        self
+    }
+
+    /// Number of rotated files to keep next to the active capture, older ones are deleted.
+    pub fn keep(mut self, count: usize) -> Self {
+        self.keep = Some(count);
+        self
+    }
+}
+
+// how long to wait after a failed rotation before trying again
+const ROTATE_RETRY: Duration = Duration::from_secs(60);
+
+pub(crate) struct CaptureWriter {
+    opts: LogCapture,
+    type_filter: Vec<TxType>,
+    reason_filter: Vec<Reason>,
+    file: *mut vapi_sys::FILE,
+    opened: Instant,
+    retry_rotate: Option<Instant>,
+}
+
+unsafe fn open_file(vsl: *mut vapi_sys::VSL_data, path: &Path) -> Result<*mut vapi_sys::FILE> {
+    let name = CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| VarnishError::VSLError(format!("Invalid file name: {}", path.display())))?;
+    // append so a restart doesn't clobber the existing capture, the file header
+    // is only written when the file is empty
+    let file = vapi_sys::VSL_WriteOpen(vsl, name.as_ptr(), 1, 0);
+    if file.is_null() {
+        let e = vapi_sys::VSL_Error(vsl);
+        let error_msg = CStr::from_ptr(e).to_string_lossy().to_string();
+        vapi_sys::VSL_ResetError(vsl);
+        return Err(VarnishError::VSLError(error_msg));
+    }
+    Ok(file)
+}
+
+impl CaptureWriter {
+    pub(crate) fn open(
+        vsl: *mut vapi_sys::VSL_data,
+        opts: LogCapture,
+        type_filter: Vec<TxType>,
+        reason_filter: Vec<Reason>,
+    ) -> Result<CaptureWriter> {
+        let file = unsafe { open_file(vsl, &opts.path)? };
+        Ok(CaptureWriter {
+            opts,
+            type_filter,
+            reason_filter,
+            file,
+            opened: Instant::now(),
+            retry_rotate: None,
+        })
+    }
+
+    fn wanted(&self, tx: &vapi_sys::VSL_transaction) -> bool {
+        let ty = TxType::from(tx.type_);
+        let reason = Reason::from(tx.reason);
+        (self.type_filter.is_empty() || self.type_filter.contains(&ty))
+            && (self.reason_filter.is_empty() || self.reason_filter.contains(&reason))
+    }
+
+    pub(crate) unsafe fn write(
+        &mut self,
+        vsl: *mut vapi_sys::VSL_data,
+        txs: *const *mut vapi_sys::VSL_transaction,
+    ) -> Result<()> {
+        if self.file.is_null() {
+            self.file = open_file(vsl, &self.opts.path)?;
+            self.opened = Instant::now();
+        }
+        // only the transactions that pass the type and reason filters, the same ones
+        // the handler gets
+        let mut kept = Vec::new();
+        let mut tx = txs;
+        while !(*tx).is_null() {
+            if self.wanted(&**tx) {
+                kept.push(*tx);
+            }
+            tx = tx.add(1);
+        }
+        if !kept.is_empty() {
+            kept.push(ptr::null_mut());
+            let file = self.file as *mut std::ffi::c_void;
+            if vapi_sys::VSL_WriteTransactions(vsl, kept.as_ptr(), file) != 0 {
+                return Err(VarnishError::VSLError(format!(
+                    "Couldn't write to {}",
+                    self.opts.path.display()
+                )));
+            }
+            // writing walked their cursors to the end, rewind them so the records
+            // can be read again by the transform
+            for tx in &kept[..kept.len() - 1] {
+                vapi_sys::VSL_ResetCursor((**tx).c);
+            }
+        }
+        if self.should_rotate() {
+            self.rotate(vsl)?;
+        }
+        Ok(())
+    }
+
+    fn should_rotate(&self) -> bool {
+        if self.retry_rotate.is_some_and(|at| Instant::now() < at) {
+            return false;
+        }
+        let too_big = self
+            .opts
+            .max_bytes
+            .map(|max| {
+                let pos = unsafe { libc::ftell(self.file as *mut libc::FILE) };
+                pos >= 0 && pos as u64 >= max
+            })
+            .unwrap_or(false);
+        let too_old = self
+            .opts
+            .max_age
+            .map(|age| self.opened.elapsed() >= age)
+            .unwrap_or(false);
+        too_big || too_old
+    }
+
+    fn rotate(&mut self, vsl: *mut vapi_sys::VSL_data) -> Result<()> {
+        let renamed = rotated_path(&self.opts.path, &[])
+            .and_then(|rotated| fs::rename(&self.opts.path, &rotated).map(|_| rotated));
+        let rotated = match renamed {
+            Ok(rotated) => rotated,
+            Err(e) => {
+                // losing the rotation is better than stopping the log, keep writing
+                // to the current file and try again later
+                error!("Couldn't rotate {}: {}", self.opts.path.display(), e);
+                self.retry_rotate = Some(Instant::now() + ROTATE_RETRY);
+                return Ok(());
+            }
+        };
+        self.retry_rotate = None;
+        self.close();
+        self.file = unsafe { open_file(vsl, &self.opts.path)? };
+        self.opened = Instant::now();
+        info!("Rotated capture to {}", rotated.display());
+        if let Some(keep) = self.opts.keep {
+            if let Err(e) = prune_rotated(&self.opts.path, keep, &[]) {
+                warn!("Couldn't remove old captures: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        if !self.file.is_null() {
+            unsafe {
+                libc::fclose(self.file as *mut libc::FILE);
+            }
+            self.file = ptr::null_mut();
+        }
+    }
+}
+
+impl Drop for CaptureWriter {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
@@ -0,0 +1,120 @@
+use regex::Regex;
+
+#[derive(Debug, Clone)]
+pub enum LogFilter {
+    ExactFilter(String),
+    PatternFilter(Regex),
+    AndFilter(Vec<LogFilter>),
+    OrFilter(Vec<LogFilter>),
+    NotFilter(Box<LogFilter>),
+}
+
+impl LogFilter {
+    pub fn exact<S: Into<String>>(t: S) -> LogFilter {
+        LogFilter::ExactFilter(t.into())
+    }
+
+    pub fn negate(self) -> LogFilter {
+        LogFilter::NotFilter(Box::new(self))
+    }
+
+    pub fn pattern(p: &str) -> Result<LogFilter, regex::Error> {
+        Ok(LogFilter::PatternFilter(Regex::new(p)?))
+    }
+
+    /// Shell-style pattern matched against the whole value, `*` matches any run of
+    /// characters and `?` any single one. Without either it's an exact match.
+    pub fn glob(g: &str) -> LogFilter {
+        if !g.contains(['*', '?']) {
+            return LogFilter::exact(g);
+        }
+        let mut re = String::from("^");
+        for c in g.chars() {
+            match c {
+                '*' => re.push_str(".*"),
+                '?' => re.push('.'),
+                c => re.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+            }
+        }
+        re.push('$');
+        LogFilter::PatternFilter(Regex::new(&re).unwrap())
+    }
+
+    /// Matches nothing, the same as an empty `or`.
+    pub fn none() -> LogFilter {
+        LogFilter::OrFilter(Vec::new())
+    }
+
+    pub fn and<F: IntoIterator<Item = LogFilter>>(filters: F) -> LogFilter {
+        LogFilter::AndFilter(filters.into_iter().collect())
+    }
+
+    pub fn or<F: IntoIterator<Item = LogFilter>>(filters: F) -> LogFilter {
+        LogFilter::OrFilter(filters.into_iter().collect())
+    }
+
+    pub fn matches(&self, value: &str) -> bool {
+        match *self {
+            LogFilter::ExactFilter(ref v) => v == value,
+            LogFilter::PatternFilter(ref p) => p.is_match(value),
+            LogFilter::AndFilter(ref filters) => {
+                for filter in filters {
+                    if !filter.matches(value) {
+                        return false;
+                    }
+                }
+                true
+            }
+            LogFilter::OrFilter(ref filters) => {
+                for filter in filters {
+                    if filter.matches(value) {
+                        return true;
+                    }
+                }
+                false
+            }
+            LogFilter::NotFilter(ref f) => !f.matches(value),
+        }
+    }
+}
+
+impl std::ops::Not for LogFilter {
+    type Output = LogFilter;
+
+    fn not(self) -> LogFilter {
+        self.negate()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_glob() {
+        let f = LogFilter::glob("x-*");
+        assert!(f.matches("x-debug"));
+        assert!(f.matches("x-"));
+        assert!(!f.matches("ax-debug"));
+        let f = LogFilter::glob("sec-ch-ua?");
+        assert!(f.matches("sec-ch-uaa"));
+        assert!(!f.matches("sec-ch-ua"));
+        let f = LogFilter::glob("a.b");
+        assert!(matches!(f, LogFilter::ExactFilter(_)));
+        assert!(f.matches("a.b"));
+        assert!(!f.matches("axb"));
+    }
+
+    #[test]
+    fn test_combinators() {
+        let allow = LogFilter::or([LogFilter::glob("*"), LogFilter::exact("host")]);
+        let deny = LogFilter::or([LogFilter::exact("cookie"), LogFilter::glob("authorization")]);
+        let f = LogFilter::and([allow, deny.negate()]);
+        assert!(f.matches("host"));
+        assert!(f.matches("x-anything"));
+        assert!(!f.matches("cookie"));
+        assert!(!f.matches("authorization"));
+        assert!(!LogFilter::none().matches("host"));
+        assert!(LogFilter::pattern("(").is_err());
+    }
+}
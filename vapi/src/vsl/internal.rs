@@ -1,12 +1,21 @@
-use super::transform::LogTransform;
-use super::{LogGrouping, LogRecord, Reason, RecordType, TxType};
+use super::capture::CaptureWriter;
+use super::tag::Tag;
+use super::transform::{nest_records, LogTransform};
+use super::view::TransactionView;
+use super::{
+    CallbackResult, LogCallback, LogGrouping, LogLine, LogRecord, LogTransaction, Reason,
+    RecordType, TxType,
+};
 use crate::error::{Result, VarnishError};
-use crate::vsl::VarnishLogBuilder;
-use crate::vsm::{vsm_status, OpenVSM};
+use crate::vsl::{LogSource, VarnishLogBuilder};
+use crate::vsm::vsm_status;
 use crossbeam_channel::{Receiver, Sender, TryRecvError};
+use std::collections::{HashMap, VecDeque};
 use std::ffi::{CStr, CString};
+use std::os::raw::c_int;
+use std::os::unix::ffi::OsStrExt;
 use std::ptr;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tracing::{error, warn};
 use vapi_sys;
 
@@ -16,6 +25,10 @@ const VSL_LENMASK: u32 = 0xffff;
 const VSL_CLIENTMARKER: u32 = 1u32 << 30;
 const VSL_BACKENDMARKER: u32 = 1u32 << 31;
 
+// returned to VSLQ_Dispatch when the handler asked to stop, the handler keeps the
+// real status so it can't be mistaken for one of varnish's own
+const DISPATCH_STOPPED: c_int = -9;
+
 #[derive(Debug)]
 pub(crate) struct Vsl {
     pub(crate) vsl: *mut vapi_sys::VSL_data,
@@ -106,9 +119,17 @@ impl Drop for VslCursor {
 }
 
 impl VslCursor {
-    fn new(vsl: &mut Vsl, vsm: &OpenVSM, opts: u32) -> Result<VslCursor> {
+    fn new(vsl: &mut Vsl, source: &LogSource, opts: u32) -> Result<VslCursor> {
         let cursor = unsafe {
-            let c = vapi_sys::VSL_CursorVSM(vsl.vsl, vsm.0.vsm, opts);
+            let c = match source {
+                LogSource::Vsm(vsm) => vapi_sys::VSL_CursorVSM(vsl.vsl, vsm.0.vsm, opts),
+                LogSource::File(path) => {
+                    let name = CString::new(path.as_os_str().as_bytes()).map_err(|_| {
+                        VarnishError::VSLError(format!("Invalid file name: {}", path.display()))
+                    })?;
+                    vapi_sys::VSL_CursorFile(vsl.vsl, name.as_ptr(), opts)
+                }
+            };
             if c.is_null() {
                 let e = vapi_sys::VSL_Error(vsl.vsl);
                 let error_msg = CStr::from_ptr(e).to_string_lossy().to_string();
@@ -137,7 +158,7 @@ pub(crate) enum CursorResult<'rec> {
 #[allow(unused)]
 pub(crate) struct TagData<'rec> {
     pub vxid: u32,
-    pub tag: &'rec CStr,
+    pub tag: Tag,
     pub data: &'rec CStr,
     pub ty: RecordType,
 }
@@ -187,8 +208,7 @@ impl VslTransaction {
             (*(*self.tx).c).rec.ptr
         };
         let header: &[u32] = unsafe { std::slice::from_raw_parts(rec_ptr, 2) };
-        let tag: u32 = header[0] >> 24;
-        let tag = unsafe { CStr::from_ptr(vapi_sys::VSL_tags[tag as usize]) };
+        let tag = Tag::from(header[0] >> 24);
 
         let vxid = header[1] & VSL_IDENTMASK;
         let data_length: u32 = header[0] & VSL_LENMASK;
@@ -227,6 +247,12 @@ impl VslTransaction {
         }
     }
 
+    pub(crate) fn reset(&mut self) {
+        unsafe {
+            vapi_sys::VSL_ResetCursor((*self.tx).c);
+        }
+    }
+
     pub(crate) fn level(&self) -> u32 {
         unsafe { (*self.tx).level }
     }
@@ -252,142 +278,399 @@ fn as_u8_slice(v: &[u32], len: u32) -> &[u8] {
     unsafe { std::slice::from_raw_parts(v.as_ptr() as *const u8, len as usize) }
 }
 
-pub(crate) fn query_loop(
-    vsm: &OpenVSM,
-    options: VarnishLogBuilder,
-    stop: Option<Receiver<()>>,
-) -> Result<()> {
-    let mut vsl = Vsl::new()?;
-    let mut vslq = if let Some(q) = options.query {
-        VslQ::new_with_query(&vsl, options.grouping, q)?
-    } else {
-        VslQ::new(&vsl, options.grouping)?
-    };
-    let mut cursor = None;
-    let callback_data = CallbackData {
-        log_sender: options.log_sender,
-        transform: options.transform,
-    };
-    loop {
-        if vsm.status() & vsm_status::VSM_WRK_RESTARTED != 0 && cursor.is_some() {
-            vslq.clear_cursor();
-            cursor = None;
+pub(crate) trait RecordSink {
+    fn send(&mut self, log: LogRecord) -> bool;
+}
+
+impl RecordSink for Sender<LogRecord> {
+    fn send(&mut self, log: LogRecord) -> bool {
+        match Sender::send(self, log) {
+            Ok(_) => true,
+            Err(e) => {
+                error!("Failed to send log data: {}", e);
+                false
+            }
         }
-        if cursor.is_none() {
-            match VslCursor::new(&mut vsl, vsm, options.cursor_opts.into()) {
-                Ok(mut c) => {
-                    vslq.set_cursor(&mut c);
-                    cursor = Some(c);
+    }
+}
+
+impl RecordSink for VecDeque<LogRecord> {
+    fn send(&mut self, log: LogRecord) -> bool {
+        self.push_back(log);
+        true
+    }
+}
+
+pub(crate) struct Transactions {
+    vsl: *mut vapi_sys::VSL_data,
+    tx: *const *mut vapi_sys::VSL_transaction,
+}
+
+impl Iterator for Transactions {
+    type Item = VslTransaction;
+
+    fn next(&mut self) -> Option<VslTransaction> {
+        if self.tx.is_null() {
+            return None;
+        }
+        let t = unsafe { *self.tx };
+        if t.is_null() {
+            return None;
+        }
+        let this_tx = match unsafe { VslTransaction::new(t, self.vsl) } {
+            Ok(t) => t,
+            Err(e) => panic!("Tried to create Tx from null data: {}", e),
+        };
+        self.tx = unsafe { self.tx.add(1) };
+        Some(this_tx)
+    }
+}
+
+pub(crate) trait Dispatch {
+    /// Handle one group of transactions from `VSLQ_Dispatch`. Returning anything
+    /// but 0 stops the dispatch and is handed back as its status.
+    fn dispatch(&mut self, txs: Transactions) -> c_int;
+
+    /// Status the handler stopped with, after `dispatch` returned `DISPATCH_STOPPED`.
+    fn take_stop(&mut self) -> Option<i32> {
+        None
+    }
+}
+
+pub(crate) struct TransformDispatch<S> {
+    pub(crate) transform: LogTransform,
+    pub(crate) sink: S,
+}
+
+impl<S: RecordSink> TransformDispatch<S> {
+    fn dispatch_nested(&mut self, txs: Transactions) -> c_int {
+        let mut records = Vec::new();
+        let mut parents = HashMap::new();
+        for tx in txs {
+            parents.insert(tx.vxid(), tx.parent_vxid());
+            match self.transform.process_txn(tx) {
+                Ok(Some(log)) => records.push(log),
+                Ok(None) => {}
+                Err(e) => {
+                    error!("Failed to process log message: {}", e);
+                    return -7;
+                }
+            }
+        }
+        for log in nest_records(records, &parents) {
+            if !self.sink.send(log) {
+                return -6;
+            }
+        }
+        0
+    }
+}
+
+impl<S: RecordSink> Dispatch for TransformDispatch<S> {
+    fn dispatch(&mut self, txs: Transactions) -> c_int {
+        if self.transform.nests() {
+            return self.dispatch_nested(txs);
+        }
+        for tx in txs {
+            match self.transform.process_txn(tx) {
+                Ok(Some(log)) => {
+                    if !self.sink.send(log) {
+                        return -6;
+                    }
                 }
+                Ok(None) => {}
                 Err(e) => {
-                    warn!("Error creating cursor: {}", e);
-                    continue;
+                    error!("Failed to process log message: {}", e);
+                    return -7;
                 }
             }
         }
-        let callback = &callback_data as *const CallbackData as *mut std::ffi::c_void;
-        let mut res = vapi_sys::vsl_status_vsl_more;
-        let mut should_stop = false;
-        while res == vapi_sys::vsl_status_vsl_more && !should_stop {
-            res = unsafe { vapi_sys::VSLQ_Dispatch(vslq.vslq, Some(rust_dispatch), callback) };
-
-            should_stop = stop
-                .as_ref()
-                .map(|r| match r.try_recv() {
-                    // received signal, stop
-                    Ok(_) => true,
-                    // didn't receive anything, keep going
-                    Err(TryRecvError::Empty) => false,
-                    // channel is closed or errored, stop
-                    _ => true,
-                })
-                // if there's no channel, don't stop ever
-                .unwrap_or(false);
+        0
+    }
+}
+
+pub(crate) struct CallbackDispatch {
+    pub(crate) callback: LogCallback,
+    pub(crate) type_filter: Vec<TxType>,
+    pub(crate) reason_filter: Vec<Reason>,
+    pub(crate) stopped: Option<i32>,
+}
+
+impl CallbackDispatch {
+    fn read_transaction(&self, mut tx: VslTransaction) -> Result<Option<LogTransaction>, i32> {
+        let ty = tx.ty();
+        let reason = tx.reason();
+        if !(self.type_filter.is_empty() || self.type_filter.contains(&ty))
+            || !(self.reason_filter.is_empty() || self.reason_filter.contains(&reason))
+        {
+            return Ok(None);
         }
-        if should_stop {
-            return Ok(());
+        let mut data = Vec::new();
+        loop {
+            match tx.read_next_record() {
+                CursorResult::NoData => break,
+                CursorResult::NoMatch => continue,
+                CursorResult::Error(e) => return Err(e),
+                CursorResult::Match(td) => data.push(LogLine {
+                    vxid: td.vxid,
+                    tag: td.tag,
+                    data: td.data.to_string_lossy().into_owned(),
+                    ty: td.ty,
+                }),
+            }
         }
+        Ok(Some(LogTransaction {
+            started: Instant::now(),
+            level: tx.level(),
+            vxid: tx.vxid(),
+            parent_vxid: tx.parent_vxid(),
+            ty,
+            reason,
+            data,
+        }))
+    }
+}
+
+impl Dispatch for CallbackDispatch {
+    fn dispatch(&mut self, txs: Transactions) -> c_int {
+        for tx in txs {
+            let txn = match self.read_transaction(tx) {
+                Ok(Some(txn)) => txn,
+                Ok(None) => continue,
+                Err(e) => {
+                    error!("Failed to read log record: {}", e);
+                    return -7;
+                }
+            };
+            if let CallbackResult::Stop(code) = (self.callback)(txn) {
+                self.stopped = Some(code);
+                return DISPATCH_STOPPED;
+            }
+        }
+        0
+    }
+
+    fn take_stop(&mut self) -> Option<i32> {
+        self.stopped.take()
+    }
+}
+
+pub(crate) struct ViewDispatch<F> {
+    pub(crate) callback: F,
+    pub(crate) type_filter: Vec<TxType>,
+    pub(crate) reason_filter: Vec<Reason>,
+    pub(crate) stopped: Option<i32>,
+}
+
+impl<F> Dispatch for ViewDispatch<F>
+where
+    F: FnMut(&mut TransactionView) -> CallbackResult,
+{
+    fn dispatch(&mut self, txs: Transactions) -> c_int {
+        for tx in txs {
+            if !(self.type_filter.is_empty() || self.type_filter.contains(&tx.ty()))
+                || !(self.reason_filter.is_empty() || self.reason_filter.contains(&tx.reason()))
+            {
+                continue;
+            }
+            let mut view = TransactionView::new(tx);
+            let res = (self.callback)(&mut view);
+            if let Some(e) = view.error() {
+                error!("Failed to read log record: {}", e);
+                return -7;
+            }
+            if let CallbackResult::Stop(code) = res {
+                self.stopped = Some(code);
+                return DISPATCH_STOPPED;
+            }
+        }
+        0
+    }
+
+    fn take_stop(&mut self) -> Option<i32> {
+        self.stopped.take()
+    }
+}
+
+pub(crate) enum ReadStatus {
+    More,
+    Idle,
+    Eof,
+}
+
+pub(crate) struct LogReader<'vsm, D> {
+    source: LogSource<'vsm>,
+    cursor_opts: u32,
+    reacquire: bool,
+    reacquire_signal: Option<Sender<()>>,
+    cursor: Option<VslCursor>,
+    // the query owns the cursor once it is set, so it has to go before the VSL
+    vslq: VslQ,
+    vsl: Vsl,
+    callback_data: CallbackData<D>,
+}
+
+impl<'vsm, D: Dispatch> LogReader<'vsm, D> {
+    pub(crate) fn new(
+        source: LogSource<'vsm>,
+        options: VarnishLogBuilder,
+        handler: D,
+    ) -> Result<LogReader<'vsm, D>> {
+        let vsl = Vsl::new()?;
+        let vslq = if let Some(q) = options.query {
+            VslQ::new_with_query(&vsl, options.grouping, q)?
+        } else {
+            VslQ::new(&vsl, options.grouping)?
+        };
+        let capture = match options.capture {
+            Some(c) => Some(CaptureWriter::open(
+                vsl.vsl,
+                c,
+                options.type_filter,
+                options.reason_filter,
+            )?),
+            None => None,
+        };
+        Ok(LogReader {
+            source,
+            cursor_opts: options.cursor_opts.into(),
+            reacquire: options.reacquire,
+            reacquire_signal: options.reacquire_signal,
+            cursor: None,
+            vslq,
+            vsl,
+            callback_data: CallbackData { capture, handler },
+        })
+    }
+
+    pub(crate) fn handler_mut(&mut self) -> &mut D {
+        &mut self.callback_data.handler
+    }
+
+    /// Run a single `VSLQ_Dispatch`, (re)acquiring the cursor first if needed.
+    pub(crate) fn dispatch(&mut self) -> Result<ReadStatus> {
+        if let LogSource::Vsm(vsm) = self.source {
+            if vsm.status() & vsm_status::VSM_WRK_RESTARTED != 0 && self.cursor.is_some() {
+                self.vslq.clear_cursor();
+                self.cursor = None;
+            }
+        }
+        if self.cursor.is_none() {
+            match VslCursor::new(&mut self.vsl, &self.source, self.cursor_opts) {
+                Ok(mut c) => {
+                    self.vslq.set_cursor(&mut c);
+                    self.cursor = Some(c);
+                }
+                // a file that can't be opened won't fix itself, so don't retry
+                Err(e) if !self.source.can_reacquire() => return Err(e),
+                Err(e) => {
+                    warn!("Error creating cursor: {}", e);
+                    return Ok(ReadStatus::Idle);
+                }
+            }
+        }
+        let callback = &mut self.callback_data as *mut CallbackData<D> as *mut std::ffi::c_void;
+        let res =
+            unsafe { vapi_sys::VSLQ_Dispatch(self.vslq.vslq, Some(rust_dispatch::<D>), callback) };
         if res == vapi_sys::vsl_status_vsl_more {
-            continue;
+            return Ok(ReadStatus::More);
         } else if res == vapi_sys::vsl_status_vsl_end {
-            std::thread::sleep(Duration::from_millis(10));
-            continue;
-        } else if res == vapi_sys::vsl_status_vsl_e_eof {
-            break;
+            return Ok(ReadStatus::Idle);
+        } else if res == DISPATCH_STOPPED {
+            if let Some(code) = self.callback_data.handler.take_stop() {
+                return Err(VarnishError::UserStatus(code));
+            }
         }
 
-        unsafe { vapi_sys::VSLQ_Flush(vslq.vslq, Some(rust_dispatch), callback) };
+        unsafe { vapi_sys::VSLQ_Flush(self.vslq.vslq, Some(rust_dispatch::<D>), callback) };
 
-        if res == vapi_sys::vsl_status_vsl_e_abandon {
-            vslq.clear_cursor();
-            cursor = None
+        if res == vapi_sys::vsl_status_vsl_e_eof {
+            Ok(ReadStatus::Eof)
+        } else if res == vapi_sys::vsl_status_vsl_e_abandon && self.source.can_reacquire() {
+            self.vslq.clear_cursor();
+            self.cursor = None;
+            Ok(ReadStatus::More)
+        } else if res == vapi_sys::vsl_status_vsl_e_abandon {
+            Err(VarnishError::VSLError("Log source abandoned".into()))
         } else if res == vapi_sys::vsl_status_vsl_e_overrun {
-            if options.reacquire {
-                vslq.clear_cursor();
-                cursor = None;
-                if let Some(tx) = options.reacquire_signal.as_ref() {
+            if self.reacquire && self.source.can_reacquire() {
+                self.vslq.clear_cursor();
+                self.cursor = None;
+                if let Some(tx) = self.reacquire_signal.as_ref() {
                     tx.send(()).map_err(|_| VarnishError::LogOverrun)?;
                 }
+                Ok(ReadStatus::More)
             } else {
-                return Err(VarnishError::LogOverrun);
+                Err(VarnishError::LogOverrun)
             }
         } else if res == vapi_sys::vsl_status_vsl_e_io {
-            return Err(VarnishError::IOError);
+            Err(VarnishError::IOError)
         } else if res == -6 {
-            return Err(VarnishError::CallbackError("Log channel error".into()));
+            Err(VarnishError::CallbackError("Log channel error".into()))
         } else if res == -7 {
-            return Err(VarnishError::CallbackError(
+            Err(VarnishError::CallbackError(
                 "Log transformation error".into(),
-            ));
+            ))
+        } else if res == -8 {
+            Err(VarnishError::CallbackError("Log capture error".into()))
         } else {
-            return Err(VarnishError::UserStatus(res));
+            Err(VarnishError::UserStatus(res))
+        }
+    }
+}
+
+pub(crate) fn query_loop<D: Dispatch>(
+    source: LogSource,
+    options: VarnishLogBuilder,
+    handler: D,
+    stop: Option<Receiver<()>>,
+) -> Result<()> {
+    let mut reader = LogReader::new(source, options, handler)?;
+    loop {
+        let status = reader.dispatch()?;
+
+        let should_stop = stop
+            .as_ref()
+            .map(|r| match r.try_recv() {
+                // received signal, stop
+                Ok(_) => true,
+                // didn't receive anything, keep going
+                Err(TryRecvError::Empty) => false,
+                // channel is closed or errored, stop
+                _ => true,
+            })
+            // if there's no channel, don't stop ever
+            .unwrap_or(false);
+        if should_stop {
+            return Ok(());
+        }
+        match status {
+            ReadStatus::More => {}
+            ReadStatus::Idle => std::thread::sleep(Duration::from_millis(10)),
+            ReadStatus::Eof => return Ok(()),
         }
     }
-    Ok(())
 }
 
 #[repr(C)]
-struct CallbackData {
-    log_sender: Sender<LogRecord>,
-    transform: LogTransform,
+struct CallbackData<D> {
+    capture: Option<CaptureWriter>,
+    handler: D,
 }
 
-#[no_mangle]
-unsafe extern "C" fn rust_dispatch(
+unsafe extern "C" fn rust_dispatch<D: Dispatch>(
     vsl: *mut vapi_sys::VSL_data,
-    mut tx: *const *mut vapi_sys::VSL_transaction,
+    tx: *const *mut vapi_sys::VSL_transaction,
     priv_: *mut std::os::raw::c_void,
 ) -> std::os::raw::c_int {
-    let callback_data: &CallbackData = &*(priv_ as *const CallbackData);
-    loop {
-        if tx.is_null() {
-            break;
-        }
-        let t = *tx;
-        if t.is_null() {
-            break;
-        }
-
-        let this_tx = match VslTransaction::new(t, vsl) {
-            Ok(t) => t,
-            Err(e) => panic!("Tried to create Tx from null data: {}", e),
-        };
-        match callback_data.transform.process_txn(this_tx) {
-            Ok(Some(log)) => match callback_data.log_sender.send(log) {
-                Ok(_) => {}
-                Err(e) => {
-                    error!("Failed to send log data: {}", e);
-                    return -6;
-                }
-            },
-            Ok(None) => {}
-            Err(e) => {
-                error!("Failed to process log message: {}", e);
-                return -7;
+    let callback_data: &mut CallbackData<D> = &mut *(priv_ as *mut CallbackData<D>);
+    if let Some(capture) = callback_data.capture.as_mut() {
+        if !tx.is_null() {
+            if let Err(e) = capture.write(vsl, tx) {
+                error!("Failed to capture log data: {}", e);
+                return -8;
             }
         }
-
-        tx = tx.add(1);
     }
-    0
+    callback_data.handler.dispatch(Transactions { vsl, tx })
 }
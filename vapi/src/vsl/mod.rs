@@ -1,23 +1,41 @@
+#[cfg(feature = "tokio")]
+pub mod async_stream;
+pub mod capture;
+pub mod filter;
 pub(crate) mod internal;
 pub mod models;
+pub mod ncsa;
 pub(crate) mod parsers;
+pub mod redact;
+pub mod stream;
+pub mod tag;
 pub mod transform;
+pub mod view;
 
 pub use models::*;
 
+use std::path::PathBuf;
 use std::time::Instant;
 
 use crate::error::Result;
 use crate::vsm::OpenVSM;
 use crossbeam_channel::{Receiver, Sender};
-use internal::query_loop;
+use internal::{query_loop, CallbackDispatch, LogReader, TransformDispatch, ViewDispatch};
+use std::collections::VecDeque;
 
 use vapi_sys;
 
+use self::capture::LogCapture;
+use self::stream::LogStream;
+use self::tag::Tag;
 use self::transform::LogTransform;
+use self::view::TransactionView;
 
 pub type LogCallback = Box<dyn Fn(LogTransaction) -> CallbackResult>;
 
+/// The client/backend marker from a record's header. It's kept apart from `Tag`
+/// because it belongs to the record rather than the tag, the same tag (`Timestamp`,
+/// `VCL_call`, `Link`...) is logged on either side.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum RecordType {
     Client,
@@ -28,7 +46,7 @@ pub enum RecordType {
 #[derive(Debug)]
 pub struct LogLine {
     pub vxid: u32,
-    pub tag: String,
+    pub tag: Tag,
     pub data: String,
     pub ty: RecordType,
 }
@@ -102,6 +120,20 @@ impl From<CursorOpts> for u32 {
     }
 }
 
+#[derive(Debug)]
+pub(crate) enum LogSource<'vsm> {
+    Vsm(&'vsm OpenVSM),
+    File(PathBuf),
+}
+
+impl LogSource<'_> {
+    // only a shared memory cursor can be recreated after an overrun or abandon,
+    // a file cursor would start over from the beginning of the file
+    pub(crate) fn can_reacquire(&self) -> bool {
+        matches!(self, LogSource::Vsm(_))
+    }
+}
+
 pub(crate) struct VarnishLogBuilder {
     pub(crate) grouping: LogGrouping,
     pub(crate) query: Option<String>,
@@ -110,12 +142,11 @@ pub(crate) struct VarnishLogBuilder {
     pub(crate) reacquire_signal: Option<Sender<()>>,
     pub(crate) type_filter: Vec<TxType>,
     pub(crate) reason_filter: Vec<Reason>,
-    pub(crate) log_sender: Sender<LogRecord>,
-    pub(crate) transform: LogTransform,
+    pub(crate) capture: Option<LogCapture>,
 }
 
 impl VarnishLogBuilder {
-    pub fn new(log_sender: Sender<LogRecord>, transform: LogTransform) -> VarnishLogBuilder {
+    pub fn new() -> VarnishLogBuilder {
         VarnishLogBuilder {
             grouping: LogGrouping::Vxid,
             query: None,
@@ -124,8 +155,7 @@ impl VarnishLogBuilder {
             reacquire_signal: None,
             type_filter: Vec::new(),
             reason_filter: Vec::new(),
-            log_sender,
-            transform,
+            capture: None,
         }
     }
     pub fn grouping(&mut self, grouping: LogGrouping) -> &mut Self {
@@ -153,6 +183,11 @@ impl VarnishLogBuilder {
         self
     }
 
+    pub fn capture(&mut self, capture: LogCapture) -> &mut Self {
+        self.capture = Some(capture);
+        self
+    }
+
     pub fn reacquire_if_overrun(&mut self) -> &mut Self {
         self.reacquire = true;
         self
@@ -164,11 +199,90 @@ impl VarnishLogBuilder {
         self
     }
 
-    pub fn execute(self, vsm: &OpenVSM, stop_channel: Option<Receiver<()>>) -> Result<()> {
-        query_loop(vsm, self, stop_channel)
+    fn apply_filters(&self, transform: &mut LogTransform) {
+        if !self.type_filter.is_empty() {
+            transform.type_filter(self.type_filter.clone());
+        }
+        if !self.reason_filter.is_empty() {
+            transform.reason_filter(self.reason_filter.clone());
+        }
+    }
+
+    pub fn execute(
+        self,
+        source: LogSource,
+        log_sender: Sender<LogRecord>,
+        mut transform: LogTransform,
+        stop_channel: Option<Receiver<()>>,
+    ) -> Result<()> {
+        self.apply_filters(&mut transform);
+        let handler = TransformDispatch {
+            transform,
+            sink: log_sender,
+        };
+        query_loop(source, self, handler, stop_channel)
+    }
+
+    pub fn execute_callback(
+        self,
+        source: LogSource,
+        callback: LogCallback,
+        stop_channel: Option<Receiver<()>>,
+    ) -> Result<()> {
+        let handler = CallbackDispatch {
+            callback,
+            type_filter: self.type_filter.clone(),
+            reason_filter: self.reason_filter.clone(),
+            stopped: None,
+        };
+        query_loop(source, self, handler, stop_channel)
+    }
+
+    pub fn execute_view<F>(
+        self,
+        source: LogSource,
+        callback: F,
+        stop_channel: Option<Receiver<()>>,
+    ) -> Result<()>
+    where
+        F: FnMut(&mut TransactionView) -> CallbackResult,
+    {
+        let handler = ViewDispatch {
+            callback,
+            type_filter: self.type_filter.clone(),
+            reason_filter: self.reason_filter.clone(),
+            stopped: None,
+        };
+        query_loop(source, self, handler, stop_channel)
+    }
+
+    pub fn stream<'vsm>(
+        self,
+        source: LogSource<'vsm>,
+        mut transform: LogTransform,
+    ) -> Result<LogStream<'vsm>> {
+        self.apply_filters(&mut transform);
+        let handler = TransformDispatch {
+            transform,
+            sink: VecDeque::new(),
+        };
+        Ok(LogStream::new(LogReader::new(source, self, handler)?))
+    }
+
+    #[cfg(feature = "tokio")]
+    pub fn stream_async(
+        self,
+        source: LogSource,
+        mut transform: LogTransform,
+        capacity: usize,
+    ) -> Result<async_stream::AsyncLogStream> {
+        self.apply_filters(&mut transform);
+        async_stream::AsyncLogStream::spawn((&source).into(), self, transform, capacity)
     }
 }
 
+/// Returned from a `LogCallback`, `Stop(code)` ends the dispatch loop with
+/// `VarnishError::UserStatus(code)`.
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum CallbackResult {
     Continue,
@@ -1,8 +1,12 @@
 use std::collections::HashMap;
+use std::net::IpAddr;
 
+use ipnet::IpNet;
 use serde::{Deserialize, Serialize};
 
-use super::parsers::{RequestAccounting, Timestamp, VarnishLink, VarnishTtl};
+use super::parsers::{
+    ExpBan, ExpKill, ObjectHit, RequestAccounting, Storage, Timestamp, VarnishLink, VarnishTtl,
+};
 
 #[derive(Debug, Clone, Deserialize)]
 #[serde(tag = "ip_source", rename_all = "snake_case")]
@@ -12,6 +16,20 @@ pub enum IpSource {
         #[serde(rename = "ip_source_header")]
         name: String,
     },
+    /// Walk a forwarding header from the right, starting at the connecting address,
+    /// and take the first hop that isn't one of the `trusted` proxies. If a trusted
+    /// proxy passed on a hop that isn't an address, `remoteip` is left unset.
+    /// `header` is X-Forwarded-For style unless it's `Forwarded` (RFC 7239).
+    TrustedProxy {
+        #[serde(rename = "ip_source_header", default = "default_forwarded_header")]
+        header: String,
+        #[serde(rename = "trusted_proxies", default)]
+        trusted: Vec<IpNet>,
+    },
+}
+
+fn default_forwarded_header() -> String {
+    String::from("x-forwarded-for")
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
@@ -78,15 +96,34 @@ pub enum CacheHandling {
     Error,
 }
 
+/// `values` holds every occurrence a header ended up with, in order. `initial` holds
+/// the occurrences it had before VCL first unset or replaced one of them.
+#[derive(Debug, Default, Serialize)]
+pub struct HeaderHistory {
+    pub values: Vec<String>,
+    pub initial: Vec<String>,
+    pub modified: bool,
+}
+
 #[derive(Debug, Serialize)]
 pub struct LogRequest {
     pub remoteip: Option<String>,
+    /// The client address before anonymization, only set if asked for. It's never
+    /// serialized, so it can't end up in the anonymized log by accident.
+    #[serde(skip)]
+    pub original_ip: Option<IpAddr>,
+    /// The user name of a `Basic` Authorization header, only set if asked for. The
+    /// header itself isn't kept for it, and like `original_ip` it's never serialized.
+    #[serde(skip)]
+    pub remote_user: Option<String>,
     pub url: String,
     pub method: String,
     pub protocol: String,
     pub headers: HashMap<String, String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub unset: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub header_history: HashMap<String, HeaderHistory>,
 }
 
 #[derive(Debug, Serialize)]
@@ -96,10 +133,59 @@ pub struct LogResponse {
     pub headers: HashMap<String, String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub unset: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub header_history: HashMap<String, HeaderHistory>,
     pub length: u64,
     pub ttl: Option<VarnishTtl>,
 }
 
+#[derive(Debug, Default, Serialize)]
+pub struct BackendInfo {
+    pub name: Option<String>,
+    pub remote_addr: Option<String>,
+    pub remote_port: Option<u16>,
+    pub local_addr: Option<String>,
+    pub local_port: Option<u16>,
+    /// Whether an existing connection was reused, only reported by newer varnish versions.
+    pub reused: Option<bool>,
+    /// Whether the connection went back to the pool (BackendReuse, or BackendClose
+    /// with "recycle") or was closed.
+    pub recycled: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub close_reason: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fetch_errors: Vec<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct CacheObject {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hit: Option<ObjectHit>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hit_miss: Option<ObjectHit>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hit_pass: Option<ObjectHit>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub storage: Option<Storage>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub bans: Vec<ExpBan>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub expiry: Vec<ExpKill>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct SessionInfo {
+    pub remote_addr: Option<String>,
+    pub remote_port: Option<u16>,
+    pub listener: Option<String>,
+    pub local_addr: Option<String>,
+    pub local_port: Option<u16>,
+    pub opened: Option<f64>,
+    pub fd: Option<i32>,
+    pub close_reason: Option<String>,
+    pub duration_msec: Option<f64>,
+}
+
 #[derive(Debug, Serialize)]
 pub struct LogRecord {
     pub level: u32,
@@ -114,7 +200,21 @@ pub struct LogRecord {
     pub response: LogResponse,
     pub link: Option<VarnishLink>,
     pub accounting: Option<RequestAccounting>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backend: Option<BackendInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_object: Option<CacheObject>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session: Option<SessionInfo>,
     pub duration_msec: Option<f64>,
     pub ttfb_msec: Option<f64>,
     pub meta: HashMap<String, String>,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub vcl_log: HashMap<String, String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub vcl_log_lines: Vec<String>,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub ext: HashMap<String, String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<LogRecord>,
 }
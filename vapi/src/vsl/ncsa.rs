@@ -0,0 +1,401 @@
+use super::models::{CacheHandling, LogRecord, TxType};
+use crate::error::{Result, VarnishError};
+use chrono::DateTime;
+use std::fmt::Write;
+
+/// varnishncsa's default, the Apache combined log format.
+pub const COMBINED: &str = r#"%h %l %u %t "%r" %s %b "%{Referer}i" "%{User-agent}i""#;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Directive {
+    Literal(String),
+    RemoteHost,
+    RemoteLog,
+    RemoteUser,
+    Time,
+    RequestLine,
+    Status,
+    Bytes,
+    BytesIn,
+    BytesOut,
+    Method,
+    Path,
+    Query,
+    Protocol,
+    DurationUsec,
+    DurationSecs,
+    ReqHeader(String),
+    RespHeader(String),
+    Handling,
+    HitMiss,
+    Side,
+    Vxid,
+    TimeFirstByte,
+    VclLog(String),
+}
+
+/// A varnishncsa log format, rendering a `LogRecord` as one access log line.
+///
+/// Headers are looked up in `LogRequest::headers` and `LogResponse::headers`, so the
+/// ones in `req_headers` and `resp_headers` need to be captured by the `LogTransform`,
+/// as does `vcl_log` if `%{VCL_Log:key}x` is used. Times are in UTC.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NcsaFormat {
+    directives: Vec<Directive>,
+}
+
+impl Default for NcsaFormat {
+    fn default() -> Self {
+        NcsaFormat::parse(COMBINED).expect("the combined format parses")
+    }
+}
+
+fn format_error(msg: String) -> VarnishError {
+    VarnishError::FormatError(msg)
+}
+
+impl NcsaFormat {
+    pub fn parse(format: &str) -> Result<NcsaFormat> {
+        let mut directives = Vec::new();
+        let mut literal = String::new();
+        let mut chars = format.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                literal.push(c);
+                continue;
+            }
+            let mut arg = None;
+            let mut d = chars
+                .next()
+                .ok_or_else(|| format_error("Format ends with %".into()))?;
+            if d == '{' {
+                let mut a = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => a.push(c),
+                        None => return Err(format_error(format!("Unterminated %{{{}", a))),
+                    }
+                }
+                arg = Some(a);
+                d = chars
+                    .next()
+                    .ok_or_else(|| format_error("Format ends after %{...}".into()))?;
+            }
+            if d == '%' && arg.is_none() {
+                literal.push('%');
+                continue;
+            }
+            let directive = match (d, arg) {
+                ('h', None) => Directive::RemoteHost,
+                ('l', None) => Directive::RemoteLog,
+                ('u', None) => Directive::RemoteUser,
+                ('t', None) => Directive::Time,
+                ('r', None) => Directive::RequestLine,
+                ('s', None) => Directive::Status,
+                ('b', None) => Directive::Bytes,
+                ('I', None) => Directive::BytesIn,
+                ('O', None) => Directive::BytesOut,
+                ('m', None) => Directive::Method,
+                ('U', None) => Directive::Path,
+                ('q', None) => Directive::Query,
+                ('H', None) => Directive::Protocol,
+                ('D', None) => Directive::DurationUsec,
+                ('T', None) => Directive::DurationSecs,
+                ('i', Some(h)) => Directive::ReqHeader(h.to_lowercase()),
+                ('o', Some(h)) => Directive::RespHeader(h.to_lowercase()),
+                ('x', Some(x)) => match x.split_once(':') {
+                    Some(("Varnish", "handling")) => Directive::Handling,
+                    Some(("Varnish", "hitmiss")) => Directive::HitMiss,
+                    Some(("Varnish", "side")) => Directive::Side,
+                    Some(("Varnish", "vxid")) => Directive::Vxid,
+                    Some(("Varnish", "time_firstbyte")) => Directive::TimeFirstByte,
+                    Some(("VCL_Log", key)) => Directive::VclLog(key.to_string()),
+                    _ => return Err(format_error(format!("Unsupported %{{{}}}x", x))),
+                },
+                (d, Some(a)) => return Err(format_error(format!("Unsupported %{{{}}}{}", a, d))),
+                (d, None) => return Err(format_error(format!("Unsupported %{}", d))),
+            };
+            if !literal.is_empty() {
+                directives.push(Directive::Literal(std::mem::take(&mut literal)));
+            }
+            directives.push(directive);
+        }
+        if !literal.is_empty() {
+            directives.push(Directive::Literal(literal));
+        }
+        Ok(NcsaFormat { directives })
+    }
+
+    /// Lowercased names of the request headers the format needs.
+    pub fn req_headers(&self) -> Vec<String> {
+        let mut headers = Vec::new();
+        for d in &self.directives {
+            match d {
+                Directive::ReqHeader(h) => headers.push(h.clone()),
+                Directive::RequestLine => headers.push("host".into()),
+                _ => {}
+            }
+        }
+        headers
+    }
+
+    /// Lowercased names of the response headers the format needs.
+    pub fn resp_headers(&self) -> Vec<String> {
+        self.directives
+            .iter()
+            .filter_map(|d| match d {
+                Directive::RespHeader(h) => Some(h.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// `%u` needs the user name from the Authorization header, see `LogTransform::remote_user`.
+    pub fn uses_remote_user(&self) -> bool {
+        self.directives
+            .iter()
+            .any(|d| matches!(d, Directive::RemoteUser))
+    }
+
+    pub fn uses_vcl_log(&self) -> bool {
+        self.directives
+            .iter()
+            .any(|d| matches!(d, Directive::VclLog(_)))
+    }
+
+    pub fn format(&self, rec: &LogRecord) -> String {
+        let mut out = String::new();
+        for d in &self.directives {
+            // writing to a String can't fail
+            let _ = self.write_directive(&mut out, d, rec);
+        }
+        out
+    }
+
+    fn write_directive(
+        &self,
+        out: &mut String,
+        d: &Directive,
+        rec: &LogRecord,
+    ) -> std::fmt::Result {
+        let req = &rec.request;
+        let backend = rec.tx_type == TxType::BackendRequest;
+        match d {
+            Directive::Literal(s) => out.push_str(s),
+            Directive::RemoteHost => out.push_str(req.remoteip.as_deref().unwrap_or("-")),
+            Directive::RemoteLog => out.push('-'),
+            Directive::RemoteUser => out.push_str(req.remote_user.as_deref().unwrap_or("-")),
+            Directive::Time => match rec.timings.get("Start") {
+                Some(start) => write_clf_time(out, start.ts)?,
+                None => out.push('-'),
+            },
+            Directive::RequestLine => {
+                let host = req.headers.get("host").map_or("localhost", |h| h.as_str());
+                if req.url.starts_with('/') {
+                    write!(
+                        out,
+                        "{} http://{}{} {}",
+                        req.method, host, req.url, req.protocol
+                    )?;
+                } else {
+                    write!(out, "{} {} {}", req.method, req.url, req.protocol)?;
+                }
+            }
+            Directive::Status => write!(out, "{}", rec.response.status)?,
+            Directive::Bytes => {
+                // the body went out to the client, or came in from the backend
+                let bytes = match &rec.accounting {
+                    Some(a) if backend => a.body_rx,
+                    Some(a) => a.body_tx,
+                    None => rec.response.length,
+                };
+                if bytes == 0 {
+                    out.push('-');
+                } else {
+                    write!(out, "{}", bytes)?;
+                }
+            }
+            Directive::BytesIn => match &rec.accounting {
+                Some(a) => write!(out, "{}", a.total_rx)?,
+                None => out.push('-'),
+            },
+            Directive::BytesOut => match &rec.accounting {
+                Some(a) => write!(out, "{}", a.total_tx)?,
+                None => out.push('-'),
+            },
+            Directive::Method => out.push_str(dash(&req.method)),
+            Directive::Path => out.push_str(dash(req.url.split('?').next().unwrap_or(""))),
+            Directive::Query => {
+                if let Some((_, q)) = req.url.split_once('?') {
+                    write!(out, "?{}", q)?;
+                }
+            }
+            Directive::Protocol => out.push_str(dash(&req.protocol)),
+            Directive::DurationUsec => match rec.duration_msec {
+                Some(ms) => write!(out, "{}", (ms * 1000.0) as u64)?,
+                None => out.push('-'),
+            },
+            Directive::DurationSecs => match rec.duration_msec {
+                Some(ms) => write!(out, "{}", (ms / 1000.0) as u64)?,
+                None => out.push('-'),
+            },
+            Directive::ReqHeader(h) => out.push_str(req.headers.get(h).map_or("-", |v| v)),
+            Directive::RespHeader(h) => {
+                out.push_str(rec.response.headers.get(h).map_or("-", |v| v))
+            }
+            Directive::Handling => out.push_str(match rec.handling {
+                Some(CacheHandling::Hit) => "hit",
+                Some(CacheHandling::Miss) => "miss",
+                Some(CacheHandling::Pass) => "pass",
+                Some(CacheHandling::Pipe) => "pipe",
+                Some(CacheHandling::Synth) => "synth",
+                Some(CacheHandling::Error) => "error",
+                None => "-",
+            }),
+            Directive::HitMiss => out.push_str(match rec.handling {
+                Some(CacheHandling::Hit) => "hit",
+                Some(CacheHandling::Miss) | Some(CacheHandling::Pass) => "miss",
+                _ => "-",
+            }),
+            Directive::Side => out.push(if backend { 'b' } else { 'c' }),
+            Directive::Vxid => write!(out, "{}", rec.vxid)?,
+            Directive::TimeFirstByte => match rec.ttfb_msec {
+                Some(ms) => write!(out, "{:.6}", ms / 1000.0)?,
+                None => out.push('-'),
+            },
+            Directive::VclLog(key) => out.push_str(rec.vcl_log.get(key).map_or("-", |v| v)),
+        }
+        Ok(())
+    }
+}
+
+fn dash(s: &str) -> &str {
+    if s.is_empty() {
+        "-"
+    } else {
+        s
+    }
+}
+
+// [10/Oct/2000:13:55:36 +0000]
+fn write_clf_time(out: &mut String, ts: f64) -> std::fmt::Result {
+    match DateTime::from_timestamp(ts as i64, 0) {
+        Some(t) => write!(out, "{}", t.format("[%d/%b/%Y:%H:%M:%S +0000]")),
+        None => write!(out, "-"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::vsl::models::{LogRequest, LogResponse};
+    use crate::vsl::parsers::{RequestAccounting, Timestamp};
+    use crate::vsl::Reason;
+    use std::collections::HashMap;
+
+    fn record() -> LogRecord {
+        let mut timings = HashMap::new();
+        timings.insert(
+            "Start".to_string(),
+            Timestamp {
+                event: "Start".into(),
+                ts: 971186136.5,
+                since_start: 0.0,
+                since_last_timestamp: 0.0,
+            },
+        );
+        LogRecord {
+            level: 1,
+            vxid: 32770,
+            parent_vxid: 0,
+            tx_type: TxType::Request,
+            reason: Reason::RxReq,
+            call_chain: Vec::new(),
+            timings,
+            handling: Some(CacheHandling::Hit),
+            request: LogRequest {
+                remoteip: Some("192.0.2.1".into()),
+                original_ip: None,
+                remote_user: Some("Aladdin".into()),
+                url: "/index.html?a=1".into(),
+                method: "GET".into(),
+                protocol: "HTTP/1.1".into(),
+                headers: HashMap::from([
+                    ("host".to_string(), "example.com".to_string()),
+                    ("referer".to_string(), "http://example.com/".to_string()),
+                ]),
+                unset: None,
+                header_history: HashMap::new(),
+            },
+            response: LogResponse {
+                status: 200,
+                protocol: "HTTP/1.1".into(),
+                headers: HashMap::new(),
+                unset: None,
+                header_history: HashMap::new(),
+                length: 0,
+                ttl: None,
+            },
+            link: None,
+            accounting: Some(RequestAccounting {
+                header_tx: 200,
+                body_tx: 2326,
+                total_tx: 2526,
+                header_rx: 80,
+                body_rx: 0,
+                total_rx: 80,
+            }),
+            backend: None,
+            cache_object: None,
+            session: None,
+            duration_msec: Some(1.25),
+            ttfb_msec: Some(0.5),
+            meta: HashMap::new(),
+            vcl_log: HashMap::from([("user".to_string(), "42".to_string())]),
+            vcl_log_lines: Vec::new(),
+            ext: HashMap::new(),
+            children: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_combined() {
+        let f = NcsaFormat::default();
+        assert_eq!(
+            f.format(&record()),
+            r#"192.0.2.1 - Aladdin [10/Oct/2000:13:55:36 +0000] "GET http://example.com/index.html?a=1 HTTP/1.1" 200 2326 "http://example.com/" "-""#
+        );
+        assert_eq!(f.req_headers(), vec!["host", "referer", "user-agent"]);
+        assert!(f.uses_remote_user());
+    }
+
+    #[test]
+    fn test_extensions() {
+        let f = NcsaFormat::parse(
+            "%D %T %{Varnish:handling}x %{Varnish:hitmiss}x %{Varnish:side}x %{VCL_Log:user}x %{VCL_Log:x}x %U%q 100%%",
+        )
+        .unwrap();
+        assert_eq!(
+            f.format(&record()),
+            "1250 0 hit hit c 42 - /index.html?a=1 100%"
+        );
+        assert!(f.uses_vcl_log());
+        assert!(NcsaFormat::parse("%Z").is_err());
+        assert!(NcsaFormat::parse("%{Varnish:nope}x").is_err());
+        assert!(NcsaFormat::parse("%{Host").is_err());
+    }
+
+    #[test]
+    fn test_clf_time() {
+        let time = |ts| {
+            let mut out = String::new();
+            write_clf_time(&mut out, ts).unwrap();
+            out
+        };
+        assert_eq!(time(0.0), "[01/Jan/1970:00:00:00 +0000]");
+        assert_eq!(time(951827696.9), "[29/Feb/2000:12:34:56 +0000]");
+        assert_eq!(time(-1.0), "[31/Dec/1969:23:59:59 +0000]");
+        assert_eq!(time(f64::MAX), "-");
+    }
+}
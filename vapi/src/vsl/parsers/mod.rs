@@ -1,9 +1,14 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
 use std::str::FromStr;
 
 use anyhow::{anyhow, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
 use nom::{
-    bytes::complete::{tag, take_till, take_until, take_while},
-    character::complete::{alphanumeric1, digit0, digit1, space0},
+    branch::alt,
+    bytes::complete::{tag, take_till, take_till1, take_until, take_while},
+    character::complete::{alphanumeric1, digit0, digit1, one_of, space0},
     combinator::{all_consuming, complete, map, map_res, opt},
     number::complete::double,
     sequence::{delimited, terminated},
@@ -32,6 +37,10 @@ fn not_whitespace(input: &str) -> IResult<&str, &str> {
     Ok((rest, val))
 }
 
+fn not_whitespace1(input: &str) -> IResult<&str, &str> {
+    terminated(take_till1(char::is_whitespace), space0).parse(input)
+}
+
 fn parse_reqstart(input: &str) -> IResult<&str, ReqStart> {
     let (rest, address) = not_whitespace(input)?;
     let (rest, port) = map_res(not_whitespace, |p| p.parse()).parse(rest)?;
@@ -58,18 +67,56 @@ fn parse_remote_ip(input: &str) -> IResult<&str, &str> {
     Ok((rest, ip))
 }
 
-pub fn remote_ip(input: &str) -> Result<String> {
+/// First address of a comma-separated list such as X-Forwarded-For, with any port
+/// or IPv6 brackets removed.
+pub fn remote_ip(input: &str) -> Result<IpAddr> {
     let (_, ip) = parse_remote_ip(input).map_err(|e| anyhow!("Invalid remote IP: {}", e))?;
-    let ip = if ip.contains('.') {
-        if let Some(idx) = ip.find(':') {
-            &ip[..idx]
-        } else {
-            ip
-        }
-    } else {
-        ip
-    };
-    Ok(ip.to_string())
+    let ip = ip.trim();
+    parse_addr(ip).ok_or_else(|| anyhow!("Invalid remote IP: {}", ip))
+}
+
+/// The user name of a `Basic` Authorization header.
+pub fn basic_auth_user(value: &str) -> Option<String> {
+    let (scheme, creds) = value.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let decoded = BASE64.decode(creds.trim()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    decoded.split_once(':').map(|(user, _)| user.to_string())
+}
+
+fn parse_addr(ip: &str) -> Option<IpAddr> {
+    ip.parse::<IpAddr>()
+        .or_else(|_| ip.parse::<SocketAddr>().map(|s| s.ip()))
+        .or_else(|_| {
+            ip.strip_prefix('[')
+                .and_then(|i| i.strip_suffix(']'))
+                .unwrap_or(ip)
+                .parse::<IpAddr>()
+        })
+        .ok()
+}
+
+/// Every hop of an X-Forwarded-For value, left to right. Hops that aren't
+/// an address, like `unknown`, are None.
+pub fn forwarded_for(input: &str) -> Vec<Option<IpAddr>> {
+    input.split(',').map(|hop| parse_addr(hop.trim())).collect()
+}
+
+/// The `for=` address of every element of an RFC 7239 Forwarded value, left to right.
+/// Obfuscated identifiers and elements without `for=` are None.
+pub fn forwarded(input: &str) -> Vec<Option<IpAddr>> {
+    input
+        .split(',')
+        .map(|element| {
+            element
+                .split(';')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(k, _)| k.trim().eq_ignore_ascii_case("for"))
+                .and_then(|(_, v)| parse_addr(v.trim().trim_matches('"')))
+        })
+        .collect()
 }
 
 fn parse_timestamp(input: &str) -> IResult<&str, Timestamp> {
@@ -266,6 +313,324 @@ pub fn ttl<'a>(tag: &'a str, value: &str) -> Result<(&'a str, VarnishTtl)> {
     Ok((tag, t))
 }
 
+#[derive(Debug, PartialEq, Eq)]
+pub struct BackendOpen {
+    pub fd: i32,
+    pub name: String,
+    pub remote_addr: String,
+    pub remote_port: u16,
+    pub local_addr: String,
+    pub local_port: u16,
+    pub reused: Option<bool>,
+}
+
+fn parse_backend_open(input: &str) -> IResult<&str, BackendOpen> {
+    let (rest, fd) = map_res(not_whitespace, |s: &str| s.parse()).parse(input)?;
+    let (rest, name) = not_whitespace(rest)?;
+    let (rest, remote_addr) = not_whitespace(rest)?;
+    let (rest, remote_port) = map_res(not_whitespace, |s: &str| s.parse()).parse(rest)?;
+    let (rest, local_addr) = not_whitespace(rest)?;
+    let (rest, local_port) = map_res(not_whitespace, |s: &str| s.parse()).parse(rest)?;
+    // newer varnish versions say whether the connection was reused
+    let (rest, kind) = opt(terminated(alt((tag("connect"), tag("reuse"))), space0)).parse(rest)?;
+    Ok((
+        rest,
+        BackendOpen {
+            fd,
+            name: name.to_string(),
+            remote_addr: remote_addr.to_string(),
+            remote_port,
+            local_addr: local_addr.to_string(),
+            local_port,
+            reused: kind.map(|k| k == "reuse"),
+        },
+    ))
+}
+
+pub fn backend_open(value: &str) -> Result<BackendOpen> {
+    let (_, bo) = complete(all_consuming(parse_backend_open))
+        .parse(value)
+        .map_err(|e| anyhow!("Invalid BackendOpen: {}", e))?;
+    Ok(bo)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct BackendConn {
+    pub fd: i32,
+    pub name: String,
+    pub reason: Option<String>,
+}
+
+fn parse_backend_conn(input: &str) -> IResult<&str, BackendConn> {
+    let (rest, fd) = map_res(not_whitespace, |s: &str| s.parse()).parse(input)?;
+    let (rest, name) = not_whitespace(rest)?;
+    let reason = rest.trim_end();
+    Ok((
+        "",
+        BackendConn {
+            fd,
+            name: name.to_string(),
+            reason: (!reason.is_empty()).then(|| reason.to_string()),
+        },
+    ))
+}
+
+/// Parses BackendClose and BackendReuse records.
+pub fn backend_conn(value: &str) -> Result<BackendConn> {
+    let (_, bc) = complete(all_consuming(parse_backend_conn))
+        .parse(value)
+        .map_err(|e| anyhow!("Invalid backend connection value: {}", e))?;
+    Ok(bc)
+}
+
+fn parse_backend_start(input: &str) -> IResult<&str, (String, u16)> {
+    let (rest, addr) = not_whitespace(input)?;
+    let (rest, port) = map_res(not_whitespace, |s: &str| s.parse()).parse(rest)?;
+    Ok((rest, (addr.to_string(), port)))
+}
+
+pub fn backend_start(value: &str) -> Result<(String, u16)> {
+    let (_, bs) = complete(all_consuming(parse_backend_start))
+        .parse(value)
+        .map_err(|e| anyhow!("Invalid BackendStart: {}", e))?;
+    Ok(bs)
+}
+
+/// Object found by a Hit, HitMiss or HitPass lookup, with the TTL, grace and keep it
+/// had left. HitMiss and HitPass only report the TTL, and older varnish versions only
+/// the object's vxid.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct ObjectHit {
+    pub vxid: u32,
+    pub ttl: Option<f64>,
+    pub grace: Option<f64>,
+    pub keep: Option<f64>,
+}
+
+fn parse_object_hit(input: &str) -> IResult<&str, ObjectHit> {
+    let (rest, vxid) = map_res(delimited(space0, digit1, space0), |s: &str| {
+        s.parse::<u32>()
+    })
+    .parse(input)?;
+    let (rest, ttl) = opt(terminated(double, space0)).parse(rest)?;
+    let (rest, grace) = opt(terminated(double, space0)).parse(rest)?;
+    let (rest, keep) = opt(terminated(double, space0)).parse(rest)?;
+    Ok((
+        rest,
+        ObjectHit {
+            vxid,
+            ttl,
+            grace,
+            keep,
+        },
+    ))
+}
+
+pub fn object_hit<'a>(tag: &'a str, value: &str) -> Result<(&'a str, ObjectHit)> {
+    let (_, hit) = complete(all_consuming(parse_object_hit))
+        .parse(value)
+        .map_err(|e| anyhow!("Invalid {} value: {}", tag, e))?;
+    Ok((tag, hit))
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Storage {
+    #[serde(rename = "type")]
+    pub ty: String,
+    pub name: String,
+}
+
+fn parse_storage(input: &str) -> IResult<&str, Storage> {
+    let (rest, ty) = delimited(space0, alphanumeric1, space0).parse(input)?;
+    let (rest, name) = terminated(take_till1(char::is_whitespace), space0).parse(rest)?;
+    Ok((
+        rest,
+        Storage {
+            ty: ty.to_string(),
+            name: name.to_string(),
+        },
+    ))
+}
+
+pub fn storage(value: &str) -> Result<Storage> {
+    let (_, st) = complete(all_consuming(parse_storage))
+        .parse(value)
+        .map_err(|e| anyhow!("Invalid Storage value: {}", e))?;
+    Ok(st)
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExpBan {
+    pub vxid: u32,
+    pub reason: String,
+}
+
+fn parse_exp_ban(input: &str) -> IResult<&str, ExpBan> {
+    let (rest, vxid) = map_res(delimited(space0, digit1, space0), |s: &str| {
+        s.parse::<u32>()
+    })
+    .parse(input)?;
+    Ok((
+        "",
+        ExpBan {
+            vxid,
+            reason: rest.trim_end().to_string(),
+        },
+    ))
+}
+
+pub fn exp_ban(value: &str) -> Result<ExpBan> {
+    let (_, ban) = complete(all_consuming(parse_exp_ban))
+        .parse(value)
+        .map_err(|e| anyhow!("Invalid ExpBan value: {}", e))?;
+    Ok(ban)
+}
+
+/// An expiry event such as `EXP_Expired x=1234 t=-1`, with its `key=value` fields.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExpKill {
+    pub event: String,
+    pub fields: HashMap<String, String>,
+}
+
+fn parse_exp_kill(input: &str) -> IResult<&str, ExpKill> {
+    let (rest, event) = terminated(take_till1(char::is_whitespace), space0).parse(input)?;
+    let fields = rest
+        .split_whitespace()
+        .filter_map(|kv| kv.split_once('='))
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    Ok((
+        "",
+        ExpKill {
+            event: event.to_string(),
+            fields,
+        },
+    ))
+}
+
+pub fn exp_kill(value: &str) -> Result<ExpKill> {
+    let (_, kill) = complete(all_consuming(parse_exp_kill))
+        .parse(value)
+        .map_err(|e| anyhow!("Invalid ExpKill value: {}", e))?;
+    Ok(kill)
+}
+
+/// A PROXY protocol header as logged by Varnish, addresses and ports are
+/// `local` for connections the proxy made on its own behalf.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Proxy {
+    pub version: u8,
+    pub client_addr: String,
+    pub client_port: String,
+    pub server_addr: String,
+    pub server_port: String,
+}
+
+fn parse_proxy(input: &str) -> IResult<&str, Proxy> {
+    let (rest, version) = map_res(not_whitespace1, |s: &str| s.parse()).parse(input)?;
+    let (rest, client_addr) = not_whitespace1(rest)?;
+    let (rest, client_port) = not_whitespace1(rest)?;
+    let (rest, server_addr) = not_whitespace1(rest)?;
+    let (rest, server_port) = not_whitespace1(rest)?;
+    Ok((
+        rest,
+        Proxy {
+            version,
+            client_addr: client_addr.to_string(),
+            client_port: client_port.to_string(),
+            server_addr: server_addr.to_string(),
+            server_port: server_port.to_string(),
+        },
+    ))
+}
+
+pub fn proxy(value: &str) -> Result<Proxy> {
+    let (_, p) = complete(all_consuming(parse_proxy))
+        .parse(value)
+        .map_err(|e| anyhow!("Invalid Proxy value: {}", e))?;
+    Ok(p)
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct SessOpen {
+    pub remote_addr: String,
+    pub remote_port: u16,
+    pub listener: String,
+    pub local_addr: String,
+    pub local_port: u16,
+    pub opened: f64,
+    pub fd: i32,
+}
+
+fn parse_sess_open(input: &str) -> IResult<&str, SessOpen> {
+    let (rest, remote_addr) = not_whitespace(input)?;
+    let (rest, remote_port) = map_res(not_whitespace, |s: &str| s.parse()).parse(rest)?;
+    let (rest, listener) = not_whitespace(rest)?;
+    let (rest, local_addr) = not_whitespace(rest)?;
+    let (rest, local_port) = map_res(not_whitespace, |s: &str| s.parse()).parse(rest)?;
+    let (rest, opened) = terminated(double, space0).parse(rest)?;
+    let (rest, fd) = map_res(not_whitespace, |s: &str| s.parse()).parse(rest)?;
+    Ok((
+        rest,
+        SessOpen {
+            remote_addr: remote_addr.to_string(),
+            remote_port,
+            listener: listener.to_string(),
+            local_addr: local_addr.to_string(),
+            local_port,
+            opened,
+            fd,
+        },
+    ))
+}
+
+pub fn sess_open(value: &str) -> Result<SessOpen> {
+    let (_, so) = complete(all_consuming(parse_sess_open))
+        .parse(value)
+        .map_err(|e| anyhow!("Invalid SessOpen value: {}", e))?;
+    Ok(so)
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct SessClose {
+    pub reason: String,
+    pub duration: f64,
+}
+
+fn parse_sess_close(input: &str) -> IResult<&str, SessClose> {
+    let (rest, reason) = not_whitespace(input)?;
+    let (rest, duration) = terminated(double, space0).parse(rest)?;
+    Ok((
+        rest,
+        SessClose {
+            reason: reason.to_string(),
+            duration,
+        },
+    ))
+}
+
+pub fn sess_close(value: &str) -> Result<SessClose> {
+    let (_, sc) = complete(all_consuming(parse_sess_close))
+        .parse(value)
+        .map_err(|e| anyhow!("Invalid SessClose value: {}", e))?;
+    Ok(sc)
+}
+
+fn parse_vcl_log(input: &str) -> IResult<&str, (String, String)> {
+    let (rest, key) = take_till1(|c: char| c == ':' || c == '=' || c.is_whitespace())(input)?;
+    let (rest, _) = space0(rest)?;
+    let (rest, _) = one_of(":=")(rest)?;
+    let (value, _) = space0(rest)?;
+    Ok(("", (key.into(), value.trim_end().into())))
+}
+
+/// Splits `std.log()` output of the form `key: value` or `key=value`, anything
+/// else is free-form text.
+pub fn vcl_log(value: &str) -> Option<(String, String)> {
+    parse_vcl_log(value).ok().map(|(_, kv)| kv)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -276,22 +641,61 @@ mod test {
             ("127.0.0.1", "127.0.0.1"),
             ("127.0.0.1:7000", "127.0.0.1"),
             (
-                "abcd:0000:1234:1:23:f33:320, 10.0.0.1",
-                "abcd:0000:1234:1:23:f33:320",
+                "abcd:0000:1234:1:23:f33:320:1, 10.0.0.1",
+                "abcd:0:1234:1:23:f33:320:1",
             ),
             ("127.0.0.1, 10.0.0.5", "127.0.0.1"),
             ("127.0.0.1,10.0.0.5,169.172.0.2", "127.0.0.1"),
+            (" 2001:db8::1 ", "2001:db8::1"),
+            ("[2001:db8::1]:443", "2001:db8::1"),
+            ("[2001:db8::1]", "2001:db8::1"),
         ];
         for (t, e) in test_cases {
-            let out = remote_ip(&t).unwrap();
-            assert_eq!(out, e);
+            let out = remote_ip(t).unwrap();
+            assert_eq!(out.to_string(), e);
         }
+        assert!(remote_ip("abcd:0000:1234:1:23:f33:320").is_err());
+        assert!(remote_ip("unknown").is_err());
+    }
+
+    #[test]
+    fn test_forwarded() {
+        let ip = |s: &str| Some(s.parse::<IpAddr>().unwrap());
+        assert_eq!(
+            forwarded_for("203.0.113.7, unknown,10.0.0.1:8080"),
+            vec![ip("203.0.113.7"), None, ip("10.0.0.1")]
+        );
+        assert_eq!(
+            forwarded(
+                r#"for=192.0.2.60;proto=http;by=203.0.113.43, For="[2001:db8:cafe::17]:4711", for=_hidden, proto=https"#
+            ),
+            vec![ip("192.0.2.60"), ip("2001:db8:cafe::17"), None, None]
+        );
+    }
+
+    #[test]
+    fn test_proxy() {
+        assert_eq!(
+            proxy("2 192.0.2.1 54321 198.51.100.1 443").unwrap(),
+            Proxy {
+                version: 2,
+                client_addr: "192.0.2.1".into(),
+                client_port: "54321".into(),
+                server_addr: "198.51.100.1".into(),
+                server_port: "443".into(),
+            }
+        );
+        assert_eq!(
+            proxy("2 local local local local").unwrap().client_addr,
+            "local"
+        );
+        assert!(proxy("2 192.0.2.1").is_err());
     }
 
     #[test]
     fn test_reqstart() {
         let test_val = "127.0.0.1 80 a0";
-        let (rest, rs) = parse_reqstart(&test_val).unwrap();
+        let (rest, rs) = parse_reqstart(test_val).unwrap();
         assert_eq!(rest, "");
         assert_eq!(
             rs,
@@ -306,7 +710,7 @@ mod test {
     #[test]
     fn test_timestamp() {
         let test_val = "Fetch: 100.0 0.0 1.5";
-        let (rest, ts) = parse_timestamp(&test_val).unwrap();
+        let (rest, ts) = parse_timestamp(test_val).unwrap();
         assert_eq!(rest, "");
         assert_eq!(
             ts,
@@ -322,7 +726,7 @@ mod test {
     #[test]
     fn test_header() {
         let test_val = "X-Foo: Bar Baz";
-        let res = parse_header(&test_val);
+        let res = parse_header(test_val);
         assert!(res.is_ok());
         assert_eq!(
             res.unwrap(),
@@ -330,42 +734,230 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_backend_open() {
+        let test_cases = vec![
+            ("31 boot.default 127.0.0.1 8080 127.0.0.1 45678", None),
+            (
+                "31 boot.default 127.0.0.1 8080 127.0.0.1 45678 connect",
+                Some(false),
+            ),
+            (
+                "31 boot.default 127.0.0.1 8080 127.0.0.1 45678 reuse",
+                Some(true),
+            ),
+        ];
+        for (t, reused) in test_cases {
+            let bo = backend_open(t).unwrap();
+            assert_eq!(
+                bo,
+                BackendOpen {
+                    fd: 31,
+                    name: "boot.default".into(),
+                    remote_addr: "127.0.0.1".into(),
+                    remote_port: 8080,
+                    local_addr: "127.0.0.1".into(),
+                    local_port: 45678,
+                    reused,
+                }
+            );
+        }
+        assert!(backend_open("31 boot.default 127.0.0.1").is_err());
+        assert!(backend_open("31 boot.default 127.0.0.1 8080 127.0.0.1 45678 bogus").is_err());
+    }
+
+    #[test]
+    fn test_backend_conn() {
+        assert_eq!(
+            backend_conn("31 boot.default").unwrap(),
+            BackendConn {
+                fd: 31,
+                name: "boot.default".into(),
+                reason: None,
+            }
+        );
+        assert_eq!(
+            backend_conn("31 boot.default close RESP_CLOSE").unwrap(),
+            BackendConn {
+                fd: 31,
+                name: "boot.default".into(),
+                reason: Some("close RESP_CLOSE".into()),
+            }
+        );
+        assert!(backend_conn("boot.default").is_err());
+    }
+
+    #[test]
+    fn test_backend_start() {
+        assert_eq!(
+            backend_start("10.0.0.2 80").unwrap(),
+            ("10.0.0.2".to_string(), 80)
+        );
+        assert!(backend_start("10.0.0.2").is_err());
+    }
+
+    #[test]
+    fn test_object_hit() {
+        let test_cases = vec![
+            (
+                "32770 119.873451 10.000000 0.000000",
+                ObjectHit {
+                    vxid: 32770,
+                    ttl: Some(119.873451),
+                    grace: Some(10.0),
+                    keep: Some(0.0),
+                },
+            ),
+            (
+                "32770 -2.500000",
+                ObjectHit {
+                    vxid: 32770,
+                    ttl: Some(-2.5),
+                    grace: None,
+                    keep: None,
+                },
+            ),
+            (
+                "32770",
+                ObjectHit {
+                    vxid: 32770,
+                    ttl: None,
+                    grace: None,
+                    keep: None,
+                },
+            ),
+        ];
+        for (t, e) in test_cases {
+            let (_, hit) = object_hit("Hit", t).unwrap();
+            assert_eq!(hit, e);
+        }
+        assert!(object_hit("Hit", "abc 1.0").is_err());
+    }
+
+    #[test]
+    fn test_storage() {
+        assert_eq!(
+            storage("malloc s0").unwrap(),
+            Storage {
+                ty: "malloc".into(),
+                name: "s0".into(),
+            }
+        );
+        assert!(storage("malloc").is_err());
+    }
+
+    #[test]
+    fn test_exp_ban() {
+        assert_eq!(
+            exp_ban("32770 banned lookup").unwrap(),
+            ExpBan {
+                vxid: 32770,
+                reason: "banned lookup".into(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_exp_kill() {
+        let kill = exp_kill("EXP_Expired x=32770 t=-1").unwrap();
+        assert_eq!(kill.event, "EXP_Expired");
+        assert_eq!(kill.fields.get("x").map(String::as_str), Some("32770"));
+        assert_eq!(kill.fields.get("t").map(String::as_str), Some("-1"));
+        let kill = exp_kill("LRU_Fail").unwrap();
+        assert_eq!(kill.event, "LRU_Fail");
+        assert!(kill.fields.is_empty());
+        assert!(exp_kill("").is_err());
+    }
+
+    #[test]
+    fn test_sess_open() {
+        assert_eq!(
+            sess_open("192.168.1.10 52814 a0 10.0.0.1 80 1700000000.123456 27").unwrap(),
+            SessOpen {
+                remote_addr: "192.168.1.10".into(),
+                remote_port: 52814,
+                listener: "a0".into(),
+                local_addr: "10.0.0.1".into(),
+                local_port: 80,
+                opened: 1700000000.123456,
+                fd: 27,
+            }
+        );
+        assert!(sess_open("192.168.1.10 52814 a0").is_err());
+    }
+
+    #[test]
+    fn test_sess_close() {
+        assert_eq!(
+            sess_close("RX_TIMEOUT 5.012").unwrap(),
+            SessClose {
+                reason: "RX_TIMEOUT".into(),
+                duration: 5.012,
+            }
+        );
+        assert!(sess_close("REM_CLOSE").is_err());
+    }
+
+    #[test]
+    fn test_vcl_log() {
+        let test_cases = vec![
+            ("ab_bucket: b", Some(("ab_bucket", "b"))),
+            ("auth=ok", Some(("auth", "ok"))),
+            ("route = shield-2 ", Some(("route", "shield-2"))),
+            ("backend: s1: sick", Some(("backend", "s1: sick"))),
+            ("empty:", Some(("empty", ""))),
+            ("cache miss for /foo", None),
+            ("request failed: timeout", None),
+            (": value", None),
+            ("", None),
+        ];
+        for (t, e) in test_cases {
+            let out = vcl_log(t);
+            assert_eq!(
+                out,
+                e.map(|(k, v): (&str, &str)| (k.to_string(), v.to_string())),
+                "{}",
+                t
+            );
+        }
+    }
+
     #[test]
     fn test_signed_number() {
         let input = "-123";
-        let res = signed_number(&input);
+        let res = signed_number(input);
         assert!(res.is_ok());
         let (rest, num) = res.unwrap();
         assert_eq!(rest, "");
         assert_eq!(num, -123i64);
 
         let input = "94938";
-        let res = signed_number(&input);
+        let res = signed_number(input);
         assert!(res.is_ok());
         let (rest, num) = res.unwrap();
         assert_eq!(rest, "");
         assert_eq!(num, 94938i64);
 
         let input = "12-32";
-        let res = signed_number(&input);
+        let res = signed_number(input);
         assert!(res.is_ok());
         let (rest, num) = res.unwrap();
         assert_eq!(rest, "-32");
         assert_eq!(num, 12i64);
 
         let input = "-";
-        let res = signed_number(&input);
+        let res = signed_number(input);
         assert!(res.is_err());
 
         let input = "";
-        let res = signed_number(&input);
+        let res = signed_number(input);
         assert!(res.is_err());
     }
 
     #[test]
     fn test_ttl_rfc() {
         let input = "RFC 60 10 -1 1312966109 1312966109 1312966109 0 60 cacheable";
-        let res = parse_ttl(&input);
+        let res = parse_ttl(input);
         assert!(res.is_ok());
         let (rest, ttl) = res.unwrap();
         assert_eq!(rest, "");
@@ -389,7 +981,7 @@ mod test {
     #[test]
     fn test_ttl_vcl() {
         let input = "VCL 120 10 0 1312966111 uncacheable";
-        let res = parse_ttl(&input);
+        let res = parse_ttl(input);
         assert!(res.is_ok());
         let (rest, ttl) = res.unwrap();
         assert_eq!(rest, "");
@@ -413,7 +1005,7 @@ mod test {
     #[test]
     fn test_ttl_hfp() {
         let input = "HFP 2 0 0 1312966113 uncacheable";
-        let res = parse_ttl(&input);
+        let res = parse_ttl(input);
         assert!(res.is_ok());
         let (rest, ttl) = res.unwrap();
         assert_eq!(rest, "");
@@ -0,0 +1,329 @@
+use super::filter::LogFilter;
+use super::models::LogRecord;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+fn keyed_hash(key: &[u8], value: &str, length: Option<usize>) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(value.as_bytes());
+    let mut hex = String::with_capacity(64);
+    for b in mac.finalize().into_bytes() {
+        let _ = write!(hex, "{:02x}", b);
+    }
+    if let Some(len) = length {
+        hex.truncate(len);
+    }
+    hex
+}
+
+/// How client addresses are anonymized before they're logged.
+#[derive(Debug, Clone)]
+pub enum IpAnonymization {
+    /// Keep only the first `v4`/`v6` bits of the address, zeroing the rest.
+    Truncate { v4: u8, v6: u8 },
+    /// Keyed HMAC-SHA256 of the address as hex, cut to `length` characters if set.
+    Hash { key: Vec<u8>, length: Option<usize> },
+}
+
+impl IpAnonymization {
+    /// Truncate IPv4 addresses to their /24 and IPv6 addresses to their /48.
+    pub fn truncate() -> IpAnonymization {
+        IpAnonymization::Truncate { v4: 24, v6: 48 }
+    }
+
+    pub fn apply(&self, ip: IpAddr) -> String {
+        match self {
+            IpAnonymization::Truncate { v4, v6 } => match ip {
+                IpAddr::V4(ip) => {
+                    let mask = u32::MAX
+                        .checked_shl(32 - u32::from((*v4).min(32)))
+                        .unwrap_or(0);
+                    Ipv4Addr::from(u32::from(ip) & mask).to_string()
+                }
+                IpAddr::V6(ip) => {
+                    let mask = u128::MAX
+                        .checked_shl(128 - u32::from((*v6).min(128)))
+                        .unwrap_or(0);
+                    Ipv6Addr::from(u128::from(ip) & mask).to_string()
+                }
+            },
+            IpAnonymization::Hash { key, length } => keyed_hash(key, &ip.to_string(), *length),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum RedactTarget {
+    /// URL query parameters whose name matches
+    QueryParam(LogFilter),
+    /// Request and response headers whose lowercased name matches
+    Header(LogFilter),
+    /// The client address, from the request or the ip source header
+    RemoteIp,
+}
+
+#[derive(Debug, Clone)]
+pub enum RedactAction {
+    Drop,
+    Mask(String),
+    /// Keyed HMAC-SHA256 of the value as hex, cut to `length` characters if set.
+    Hash {
+        length: Option<usize>,
+    },
+}
+
+#[derive(Debug, Clone)]
+struct RedactRule {
+    target: RedactTarget,
+    action: RedactAction,
+}
+
+/// Rewrites sensitive values on a `LogRecord` before it leaves `LogTransform`.
+/// Rules are applied in the order they were added.
+#[derive(Debug, Clone)]
+pub struct Redactor {
+    key: Vec<u8>,
+    rules: Vec<RedactRule>,
+}
+
+impl Redactor {
+    /// `key` is used for `RedactAction::Hash`, so equal values can still be correlated
+    /// without being reversible by anyone who doesn't hold it.
+    pub fn new<K: Into<Vec<u8>>>(key: K) -> Redactor {
+        Redactor {
+            key: key.into(),
+            rules: Vec::new(),
+        }
+    }
+
+    pub fn rule(mut self, target: RedactTarget, action: RedactAction) -> Self {
+        self.rules.push(RedactRule { target, action });
+        self
+    }
+
+    // None means drop the value
+    fn redact(&self, action: &RedactAction, value: &str) -> Option<String> {
+        match action {
+            RedactAction::Drop => None,
+            RedactAction::Mask(m) => Some(m.clone()),
+            RedactAction::Hash { length } => Some(keyed_hash(&self.key, value, *length)),
+        }
+    }
+
+    pub fn apply(&self, rec: &mut LogRecord) {
+        for rule in &self.rules {
+            match &rule.target {
+                RedactTarget::QueryParam(names) => {
+                    rec.request.url = self.redact_query(&rec.request.url, names, &rule.action);
+                }
+                RedactTarget::Header(names) => {
+                    self.redact_headers(&mut rec.request.headers, names, &rule.action);
+                    self.redact_headers(&mut rec.response.headers, names, &rule.action);
+                    for history in [
+                        &mut rec.request.header_history,
+                        &mut rec.response.header_history,
+                    ] {
+                        history.retain(|name, h| {
+                            if !names.matches(name) {
+                                return true;
+                            }
+                            if let RedactAction::Drop = rule.action {
+                                return false;
+                            }
+                            for v in h.values.iter_mut().chain(h.initial.iter_mut()) {
+                                *v = self.redact(&rule.action, v).unwrap_or_default();
+                            }
+                            true
+                        });
+                    }
+                    for unset in [&mut rec.request.unset, &mut rec.response.unset]
+                        .into_iter()
+                        .flatten()
+                    {
+                        self.redact_header_lines(unset, names, &rule.action);
+                    }
+                }
+                RedactTarget::RemoteIp => {
+                    rec.request.remoteip = rec
+                        .request
+                        .remoteip
+                        .take()
+                        .and_then(|ip| self.redact(&rule.action, &ip));
+                    if let Some(session) = rec.session.as_mut() {
+                        session.remote_addr = session
+                            .remote_addr
+                            .take()
+                            .and_then(|ip| self.redact(&rule.action, &ip));
+                    }
+                }
+            }
+        }
+    }
+
+    fn redact_query(&self, url: &str, names: &LogFilter, action: &RedactAction) -> String {
+        let (path, query) = match url.split_once('?') {
+            Some(parts) => parts,
+            None => return url.to_string(),
+        };
+        let params: Vec<String> = query
+            .split('&')
+            .filter_map(|param| {
+                let (name, value) = param.split_once('=').unwrap_or((param, ""));
+                if !names.matches(name) {
+                    return Some(param.to_string());
+                }
+                self.redact(action, value)
+                    .map(|v| format!("{}={}", name, v))
+            })
+            .collect();
+        if params.is_empty() {
+            path.to_string()
+        } else {
+            format!("{}?{}", path, params.join("&"))
+        }
+    }
+
+    fn redact_headers(
+        &self,
+        headers: &mut HashMap<String, String>,
+        names: &LogFilter,
+        action: &RedactAction,
+    ) {
+        headers.retain(|name, value| {
+            if !names.matches(name) {
+                return true;
+            }
+            match self.redact(action, value) {
+                Some(v) => {
+                    *value = v;
+                    true
+                }
+                None => false,
+            }
+        });
+    }
+
+    // *Unset records keep the whole "Name: value" line
+    fn redact_header_lines(
+        &self,
+        lines: &mut Vec<String>,
+        names: &LogFilter,
+        action: &RedactAction,
+    ) {
+        lines.retain_mut(|line| {
+            let (name, value) = match line.split_once(':') {
+                Some((n, v)) => (n.trim(), v.trim()),
+                None => return true,
+            };
+            // names are matched lowercased, but logged as they were
+            if !names.matches(&name.to_lowercase()) {
+                return true;
+            }
+            match self.redact(action, value) {
+                Some(v) => {
+                    *line = format!("{}: {}", name, v);
+                    true
+                }
+                None => false,
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_redact_query() {
+        let names = LogFilter::or([LogFilter::exact("token"), LogFilter::glob("utm_*")]);
+        let drop = Redactor::new("k");
+        assert_eq!(
+            drop.redact_query(
+                "/a?token=abc&id=1&utm_source=x",
+                &names,
+                &RedactAction::Drop
+            ),
+            "/a?id=1"
+        );
+        assert_eq!(
+            drop.redact_query("/a?token=abc", &names, &RedactAction::Drop),
+            "/a"
+        );
+        assert_eq!(drop.redact_query("/a", &names, &RedactAction::Drop), "/a");
+        assert_eq!(
+            drop.redact_query(
+                "/a?id=1&token=abc",
+                &names,
+                &RedactAction::Mask("xxx".into())
+            ),
+            "/a?id=1&token=xxx"
+        );
+    }
+
+    #[test]
+    fn test_redact_hash() {
+        let r = Redactor::new("key");
+        // HMAC-SHA256("key", "The quick brown fox jumps over the lazy dog")
+        let full = r
+            .redact(
+                &RedactAction::Hash { length: None },
+                "The quick brown fox jumps over the lazy dog",
+            )
+            .unwrap();
+        assert_eq!(
+            full,
+            "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+        let short = r
+            .redact(
+                &RedactAction::Hash { length: Some(12) },
+                "The quick brown fox jumps over the lazy dog",
+            )
+            .unwrap();
+        assert_eq!(short, "f7bc83f43053");
+        let other = Redactor::new("other")
+            .redact(&RedactAction::Hash { length: None }, "value")
+            .unwrap();
+        assert_ne!(
+            other,
+            r.redact(&RedactAction::Hash { length: None }, "value")
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_ip_anonymization() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        let t = IpAnonymization::truncate();
+        assert_eq!(t.apply(ip("192.168.17.201")), "192.168.17.0");
+        assert_eq!(t.apply(ip("2001:db8:abcd:12::1")), "2001:db8:abcd::");
+        let t = IpAnonymization::Truncate { v4: 0, v6: 128 };
+        assert_eq!(t.apply(ip("192.168.17.201")), "0.0.0.0");
+        assert_eq!(t.apply(ip("2001:db8::1")), "2001:db8::1");
+        let h = IpAnonymization::Hash {
+            key: b"key".to_vec(),
+            length: Some(16),
+        };
+        assert_eq!(h.apply(ip("10.0.0.1")).len(), 16);
+        assert_eq!(h.apply(ip("10.0.0.1")), h.apply(ip("10.0.0.1")));
+        assert_ne!(h.apply(ip("10.0.0.1")), h.apply(ip("10.0.0.2")));
+    }
+
+    #[test]
+    fn test_redact_header_lines() {
+        let r = Redactor::new("k");
+        let mut lines = vec!["Cookie: a=1".to_string(), "Host: example.com".to_string()];
+        r.redact_header_lines(
+            &mut lines,
+            &LogFilter::exact("cookie"),
+            &RedactAction::Mask("-".into()),
+        );
+        assert_eq!(lines, vec!["Cookie: -", "Host: example.com"]);
+        r.redact_header_lines(&mut lines, &LogFilter::exact("cookie"), &RedactAction::Drop);
+        assert_eq!(lines, vec!["Host: example.com"]);
+    }
+}
@@ -0,0 +1,47 @@
+use super::internal::{LogReader, ReadStatus, TransformDispatch};
+use super::LogRecord;
+use crate::error::Result;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Blocking iterator over log records. Records are read from Varnish as they are
+/// requested, and the cursor is released when the stream is dropped.
+pub struct LogStream<'vsm> {
+    reader: LogReader<'vsm, TransformDispatch<VecDeque<LogRecord>>>,
+    done: bool,
+}
+
+impl<'vsm> LogStream<'vsm> {
+    pub(crate) fn new(
+        reader: LogReader<'vsm, TransformDispatch<VecDeque<LogRecord>>>,
+    ) -> LogStream<'vsm> {
+        LogStream {
+            reader,
+            done: false,
+        }
+    }
+}
+
+impl Iterator for LogStream<'_> {
+    type Item = Result<LogRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(log) = self.reader.handler_mut().sink.pop_front() {
+                return Some(Ok(log));
+            }
+            if self.done {
+                return None;
+            }
+            match self.reader.dispatch() {
+                Ok(ReadStatus::More) => {}
+                Ok(ReadStatus::Idle) => std::thread::sleep(Duration::from_millis(10)),
+                Ok(ReadStatus::Eof) => self.done = true,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
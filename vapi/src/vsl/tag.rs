@@ -0,0 +1,170 @@
+use crate::error::VarnishError;
+use std::fmt;
+use std::str::FromStr;
+
+macro_rules! vsl_tags {
+    ($($name:ident => $value:ident,)*) => {
+        /// A VSL record tag, with the same names as varnishlog uses.
+        #[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+        #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+        pub enum Tag {
+            $($name,)*
+            /// Anything this version of the bindings doesn't know about.
+            Unknown(u32),
+        }
+
+        impl Tag {
+            pub const ALL: &'static [Tag] = &[$(Tag::$name,)*];
+
+            pub fn as_str(&self) -> &'static str {
+                match self {
+                    $(Tag::$name => stringify!($name),)*
+                    Tag::Unknown(_) => "Unknown",
+                }
+            }
+        }
+
+        impl From<u32> for Tag {
+            fn from(t: u32) -> Tag {
+                match t {
+                    $(vapi_sys::$value => Tag::$name,)*
+                    _ => Tag::Unknown(t),
+                }
+            }
+        }
+    };
+}
+
+vsl_tags! {
+    Debug => VSL_tag_e_SLT_Debug,
+    Error => VSL_tag_e_SLT_Error,
+    CLI => VSL_tag_e_SLT_CLI,
+    SessOpen => VSL_tag_e_SLT_SessOpen,
+    SessClose => VSL_tag_e_SLT_SessClose,
+    BackendOpen => VSL_tag_e_SLT_BackendOpen,
+    BackendReuse => VSL_tag_e_SLT_BackendReuse,
+    BackendClose => VSL_tag_e_SLT_BackendClose,
+    HttpGarbage => VSL_tag_e_SLT_HttpGarbage,
+    Proxy => VSL_tag_e_SLT_Proxy,
+    ProxyGarbage => VSL_tag_e_SLT_ProxyGarbage,
+    Backend => VSL_tag_e_SLT_Backend,
+    Length => VSL_tag_e_SLT_Length,
+    FetchError => VSL_tag_e_SLT_FetchError,
+    ReqMethod => VSL_tag_e_SLT_ReqMethod,
+    ReqURL => VSL_tag_e_SLT_ReqURL,
+    ReqProtocol => VSL_tag_e_SLT_ReqProtocol,
+    ReqStatus => VSL_tag_e_SLT_ReqStatus,
+    ReqReason => VSL_tag_e_SLT_ReqReason,
+    ReqHeader => VSL_tag_e_SLT_ReqHeader,
+    ReqUnset => VSL_tag_e_SLT_ReqUnset,
+    ReqLost => VSL_tag_e_SLT_ReqLost,
+    RespMethod => VSL_tag_e_SLT_RespMethod,
+    RespURL => VSL_tag_e_SLT_RespURL,
+    RespProtocol => VSL_tag_e_SLT_RespProtocol,
+    RespStatus => VSL_tag_e_SLT_RespStatus,
+    RespReason => VSL_tag_e_SLT_RespReason,
+    RespHeader => VSL_tag_e_SLT_RespHeader,
+    RespUnset => VSL_tag_e_SLT_RespUnset,
+    RespLost => VSL_tag_e_SLT_RespLost,
+    BereqMethod => VSL_tag_e_SLT_BereqMethod,
+    BereqURL => VSL_tag_e_SLT_BereqURL,
+    BereqProtocol => VSL_tag_e_SLT_BereqProtocol,
+    BereqStatus => VSL_tag_e_SLT_BereqStatus,
+    BereqReason => VSL_tag_e_SLT_BereqReason,
+    BereqHeader => VSL_tag_e_SLT_BereqHeader,
+    BereqUnset => VSL_tag_e_SLT_BereqUnset,
+    BereqLost => VSL_tag_e_SLT_BereqLost,
+    BerespMethod => VSL_tag_e_SLT_BerespMethod,
+    BerespURL => VSL_tag_e_SLT_BerespURL,
+    BerespProtocol => VSL_tag_e_SLT_BerespProtocol,
+    BerespStatus => VSL_tag_e_SLT_BerespStatus,
+    BerespReason => VSL_tag_e_SLT_BerespReason,
+    BerespHeader => VSL_tag_e_SLT_BerespHeader,
+    BerespUnset => VSL_tag_e_SLT_BerespUnset,
+    BerespLost => VSL_tag_e_SLT_BerespLost,
+    ObjMethod => VSL_tag_e_SLT_ObjMethod,
+    ObjURL => VSL_tag_e_SLT_ObjURL,
+    ObjProtocol => VSL_tag_e_SLT_ObjProtocol,
+    ObjStatus => VSL_tag_e_SLT_ObjStatus,
+    ObjReason => VSL_tag_e_SLT_ObjReason,
+    ObjHeader => VSL_tag_e_SLT_ObjHeader,
+    ObjUnset => VSL_tag_e_SLT_ObjUnset,
+    ObjLost => VSL_tag_e_SLT_ObjLost,
+    BogoHeader => VSL_tag_e_SLT_BogoHeader,
+    LostHeader => VSL_tag_e_SLT_LostHeader,
+    TTL => VSL_tag_e_SLT_TTL,
+    Fetch_Body => VSL_tag_e_SLT_Fetch_Body,
+    VCL_acl => VSL_tag_e_SLT_VCL_acl,
+    VCL_call => VSL_tag_e_SLT_VCL_call,
+    VCL_trace => VSL_tag_e_SLT_VCL_trace,
+    VCL_return => VSL_tag_e_SLT_VCL_return,
+    ReqStart => VSL_tag_e_SLT_ReqStart,
+    Hit => VSL_tag_e_SLT_Hit,
+    HitPass => VSL_tag_e_SLT_HitPass,
+    ExpBan => VSL_tag_e_SLT_ExpBan,
+    ExpKill => VSL_tag_e_SLT_ExpKill,
+    WorkThread => VSL_tag_e_SLT_WorkThread,
+    ESI_xmlerror => VSL_tag_e_SLT_ESI_xmlerror,
+    Hash => VSL_tag_e_SLT_Hash,
+    Backend_health => VSL_tag_e_SLT_Backend_health,
+    VCL_Log => VSL_tag_e_SLT_VCL_Log,
+    VCL_Error => VSL_tag_e_SLT_VCL_Error,
+    Gzip => VSL_tag_e_SLT_Gzip,
+    Link => VSL_tag_e_SLT_Link,
+    Begin => VSL_tag_e_SLT_Begin,
+    End => VSL_tag_e_SLT_End,
+    VSL => VSL_tag_e_SLT_VSL,
+    Storage => VSL_tag_e_SLT_Storage,
+    Timestamp => VSL_tag_e_SLT_Timestamp,
+    ReqAcct => VSL_tag_e_SLT_ReqAcct,
+    PipeAcct => VSL_tag_e_SLT_PipeAcct,
+    BereqAcct => VSL_tag_e_SLT_BereqAcct,
+    VfpAcct => VSL_tag_e_SLT_VfpAcct,
+    Witness => VSL_tag_e_SLT_Witness,
+    BackendStart => VSL_tag_e_SLT_BackendStart,
+    H2RxHdr => VSL_tag_e_SLT_H2RxHdr,
+    H2RxBody => VSL_tag_e_SLT_H2RxBody,
+    H2TxHdr => VSL_tag_e_SLT_H2TxHdr,
+    H2TxBody => VSL_tag_e_SLT_H2TxBody,
+    HitMiss => VSL_tag_e_SLT_HitMiss,
+    Filters => VSL_tag_e_SLT_Filters,
+    SessError => VSL_tag_e_SLT_SessError,
+    VCL_use => VSL_tag_e_SLT_VCL_use,
+    Notice => VSL_tag_e_SLT_Notice,
+}
+
+impl fmt::Display for Tag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Tag {
+    type Err = VarnishError;
+
+    /// Tag names are matched case-insensitively, like `varnishlog -i`.
+    fn from_str(s: &str) -> Result<Tag, VarnishError> {
+        Tag::ALL
+            .iter()
+            .find(|t| t.as_str().eq_ignore_ascii_case(s))
+            .copied()
+            .ok_or_else(|| VarnishError::VSLError(format!("Unknown tag: {}", s)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_tag_round_trip() {
+        assert_eq!(Tag::from(vapi_sys::VSL_tag_e_SLT_VCL_call), Tag::VCL_call);
+        assert_eq!(Tag::from(vapi_sys::VSL_tag_e_SLT_Notice), Tag::Notice);
+        assert_eq!(Tag::from(4000), Tag::Unknown(4000));
+        for tag in Tag::ALL {
+            assert_eq!(tag.to_string().parse::<Tag>().unwrap(), *tag);
+        }
+        assert_eq!("reqheader".parse::<Tag>().unwrap(), Tag::ReqHeader);
+        assert!("NotATag".parse::<Tag>().is_err());
+    }
+}
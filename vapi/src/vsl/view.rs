@@ -0,0 +1,94 @@
+use super::internal::{CursorResult, VslTransaction};
+use super::tag::Tag;
+use super::{Reason, RecordType, TxType};
+
+/// A transaction handed to a view callback. Records are read straight out of
+/// varnish's buffers, so nothing is copied or allocated unless the callback does it.
+#[derive(Debug)]
+pub struct TransactionView {
+    tx: VslTransaction,
+    error: Option<i32>,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct RecordView<'a> {
+    pub vxid: u32,
+    pub tag: Tag,
+    pub ty: RecordType,
+    pub data: &'a [u8],
+}
+
+impl<'a> RecordView<'a> {
+    pub fn as_str(&self) -> Option<&'a str> {
+        std::str::from_utf8(self.data).ok()
+    }
+}
+
+impl TransactionView {
+    pub(crate) fn new(tx: VslTransaction) -> TransactionView {
+        TransactionView { tx, error: None }
+    }
+
+    pub(crate) fn error(&self) -> Option<i32> {
+        self.error
+    }
+
+    pub fn level(&self) -> u32 {
+        self.tx.level()
+    }
+
+    pub fn vxid(&self) -> u32 {
+        self.tx.vxid()
+    }
+
+    pub fn parent_vxid(&self) -> u32 {
+        self.tx.parent_vxid()
+    }
+
+    pub fn ty(&self) -> TxType {
+        self.tx.ty()
+    }
+
+    pub fn reason(&self) -> Reason {
+        self.tx.reason()
+    }
+
+    /// Iterate the transaction's records from the start, can be called repeatedly.
+    pub fn records(&mut self) -> Records<'_> {
+        self.tx.reset();
+        Records { view: self }
+    }
+}
+
+pub struct Records<'a> {
+    view: &'a mut TransactionView,
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = RecordView<'a>;
+
+    fn next(&mut self) -> Option<RecordView<'a>> {
+        loop {
+            match self.view.tx.read_next_record() {
+                CursorResult::NoData => return None,
+                CursorResult::NoMatch => continue,
+                CursorResult::Error(e) => {
+                    self.view.error = Some(e);
+                    return None;
+                }
+                CursorResult::Match(td) => {
+                    let data = td.data.to_bytes();
+                    // the record stays in the VSLQ's buffers until the dispatch callback
+                    // returns, moving the cursor along doesn't invalidate it
+                    let data = unsafe { std::slice::from_raw_parts(data.as_ptr(), data.len()) };
+                    return Some(RecordView {
+                        vxid: td.vxid,
+                        tag: td.tag,
+                        ty: td.ty,
+                        data,
+                    });
+                }
+            }
+        }
+    }
+}
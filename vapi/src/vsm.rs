@@ -33,11 +33,38 @@ impl VSMBuilder {
     }
 
     pub fn attach(mut self) -> Result<OpenVSM> {
+        let args = VsmArgs {
+            path: self.path.clone(),
+            timeout: self.timeout,
+        };
         if let Some(p) = self.path {
             self.vsm.set_path(p)?;
         }
         self.vsm.set_timeout(self.timeout)?;
-        self.vsm.attach()
+        self.vsm.attach(args)
+    }
+}
+
+// what an OpenVSM was attached with, so another handle to the same instance
+// can be opened on a different thread
+#[derive(Debug, Clone)]
+#[cfg_attr(not(feature = "tokio"), allow(dead_code))]
+pub(crate) struct VsmArgs {
+    path: Option<String>,
+    timeout: Option<Duration>,
+}
+
+impl VsmArgs {
+    #[cfg_attr(not(feature = "tokio"), allow(dead_code))]
+    pub(crate) fn attach(&self) -> Result<OpenVSM> {
+        let mut builder = VSMBuilder::new()?;
+        if let Some(p) = &self.path {
+            builder.path(p.as_str());
+        }
+        if let Some(t) = self.timeout {
+            builder.timeout(t);
+        }
+        builder.attach()
     }
 }
 
@@ -80,13 +107,13 @@ impl SharedMem {
         Ok(())
     }
 
-    fn attach(self) -> Result<OpenVSM> {
+    fn attach(self, args: VsmArgs) -> Result<OpenVSM> {
         unsafe {
             if vapi_sys::VSM_Attach(self.vsm, -1) != 0 {
                 return Err(VarnishError::from_vsm_error(&self));
             }
         }
-        Ok(OpenVSM(self))
+        Ok(OpenVSM(self, args))
     }
 }
 
@@ -101,9 +128,11 @@ impl Drop for SharedMem {
 }
 
 #[derive(Debug)]
-pub struct OpenVSM(pub(crate) SharedMem);
+pub struct OpenVSM(
+    pub(crate) SharedMem,
+    #[cfg_attr(not(feature = "tokio"), allow(dead_code))] pub(crate) VsmArgs,
+);
 
-#[allow(dead_code)]
 pub mod vsm_status {
     pub const VSM_MGT_RUNNING: u32 = 1 << 1;
     pub const VSM_MGT_CHANGED: u32 = 1 << 2;